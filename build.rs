@@ -0,0 +1,23 @@
+use cfg_aliases::cfg_aliases;
+
+fn main() {
+    cfg_aliases! {
+        kqueue: {
+            any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "dragonfly",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            )
+        },
+        debug_ptrace: {
+            all(
+                target_os = "linux",
+                target_arch = "x86_64",
+                any(target_env = "gnu", target_env = "musl")
+            )
+        },
+    }
+}
@@ -0,0 +1,187 @@
+//! `--child-affinity-strategy`: pinning the child to one kind of core on heterogeneous CPUs.
+//!
+//! Cores are grouped by `topology/cluster_id`, and clusters are ranked by the
+//! `cpu_capacity` the kernel reports on ARM (falling back to the maximum frequency).
+//! Where the kernel reports no cluster, a cluster id of `-1` as on most x86 systems,
+//! every core is ranked on its own.
+
+use anyhow::Result;
+use nix::sched::CpuSet;
+use std::fs;
+use std::path::Path;
+
+/// Which cores the child may run on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum AffinityStrategy {
+    /// The cluster(s) with the highest capacity, for latency-sensitive programs.
+    Performance,
+    /// The cluster(s) with the lowest capacity, for background tools.
+    Efficiency,
+    /// Any core, as scheduled by the kernel.
+    Any,
+}
+
+/// An online core and what is known about it.
+struct Core {
+    cpu: usize,
+    /// Cluster of the core, `None` if the kernel reports none
+    cluster: Option<u32>,
+    capacity: u64,
+}
+
+/// Returns the CPU set implementing `strategy`, or `None` if the child should not be pinned.
+///
+/// # Arguments
+/// * `strategy` - Requested kind of cores
+pub fn cpu_set(strategy: AffinityStrategy) -> Result<Option<CpuSet>> {
+    if strategy == AffinityStrategy::Any {
+        return Ok(None);
+    }
+
+    let cores = read_topology(Path::new("/sys/devices/system/cpu"))?;
+    let Some(cpus) = select_cores(&cores, strategy) else {
+        let reason = if cores.is_empty() {
+            "the kernel reports no CPU topology"
+        } else if cores.iter().all(|core| core.capacity == 0) {
+            "the kernel reports neither the capacity nor the frequency of the cores"
+        } else {
+            "all cores have the same capacity"
+        };
+        eprintln!("cli-keyhook: warning: {reason}, ignoring --child-affinity-strategy");
+        return Ok(None);
+    };
+
+    let mut set = CpuSet::new();
+    for cpu in cpus {
+        set.set(cpu)?;
+    }
+    Ok(Some(set))
+}
+
+/// Reads the cluster and capacity of every online core below `sysfs`.
+fn read_topology(sysfs: &Path) -> Result<Vec<Core>> {
+    let read_number =
+        |path: &Path| -> Option<u64> { fs::read_to_string(path).ok()?.trim().parse().ok() };
+
+    let mut cores = Vec::new();
+    for entry in fs::read_dir(sysfs)?.flatten() {
+        let Some(cpu) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("cpu"))
+            .and_then(|n| n.parse().ok())
+        else {
+            continue;
+        };
+
+        // Offline cores have no topology
+        let dir = entry.path();
+        if !dir.join("topology").is_dir() {
+            continue;
+        }
+        let cluster = fs::read_to_string(dir.join("topology/cluster_id"))
+            .ok()
+            .and_then(|id| id.trim().parse::<u32>().ok());
+        let capacity = read_number(&dir.join("cpu_capacity"))
+            .or_else(|| read_number(&dir.join("cpufreq/cpuinfo_max_freq")))
+            .unwrap_or(0);
+
+        cores.push(Core {
+            cpu,
+            cluster,
+            capacity,
+        });
+    }
+
+    Ok(cores)
+}
+
+/// Picks the cores of the clusters with the highest or lowest capacity.
+///
+/// # Returns
+/// The selected CPU numbers, or `None` if all clusters have the same capacity
+fn select_cores(cores: &[Core], strategy: AffinityStrategy) -> Option<Vec<usize>> {
+    // The capacity of a cluster is the one of its best core
+    let cluster_capacity = |core: &Core| match core.cluster {
+        Some(cluster) => cores
+            .iter()
+            .filter(|other| other.cluster == Some(cluster))
+            .map(|other| other.capacity)
+            .max()
+            .unwrap_or(0),
+        None => core.capacity,
+    };
+    let capacities: Vec<u64> = cores.iter().map(cluster_capacity).collect();
+
+    let (min, max) = (*capacities.iter().min()?, *capacities.iter().max()?);
+    if min == max {
+        return None;
+    }
+    let wanted = match strategy {
+        AffinityStrategy::Performance => max,
+        AffinityStrategy::Efficiency => min,
+        AffinityStrategy::Any => return None,
+    };
+
+    Some(
+        cores
+            .iter()
+            .zip(capacities)
+            .filter(|(_, capacity)| *capacity == wanted)
+            .map(|(core, _)| core.cpu)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_are_ranked_by_capacity() {
+        let core = |cpu, cluster, capacity| Core {
+            cpu,
+            cluster: Some(cluster),
+            capacity,
+        };
+        // Four little cores, three big ones and a prime core in a cluster of its own
+        let cores = [
+            core(0, 0, 446),
+            core(1, 0, 446),
+            core(2, 0, 446),
+            core(3, 0, 446),
+            core(4, 1, 871),
+            core(5, 1, 871),
+            core(6, 1, 871),
+            core(7, 2, 1024),
+        ];
+
+        assert_eq!(
+            select_cores(&cores, AffinityStrategy::Performance),
+            Some(vec![7])
+        );
+        assert_eq!(
+            select_cores(&cores, AffinityStrategy::Efficiency),
+            Some(vec![0, 1, 2, 3])
+        );
+        assert_eq!(
+            select_cores(&cores[..4], AffinityStrategy::Performance),
+            None
+        );
+
+        // Hybrid x86 cores without clusters, told apart by their frequency
+        let cores: Vec<Core> = [5_400_000, 5_400_000, 4_300_000, 4_300_000]
+            .into_iter()
+            .enumerate()
+            .map(|(cpu, capacity)| Core {
+                cpu,
+                cluster: None,
+                capacity,
+            })
+            .collect();
+        assert_eq!(
+            select_cores(&cores, AffinityStrategy::Efficiency),
+            Some(vec![2, 3])
+        );
+    }
+}
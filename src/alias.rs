@@ -0,0 +1,299 @@
+//! `--keymap-alias-file`: named byte sequences referenced as `$NAME` in `--keymap` values.
+//!
+//! The file defines one alias per line as `NAME=VALUE`, with blank lines and lines
+//! starting with `#` ignored. VALUE is hex like `1b5d30`, or an escaped string like
+//! `\e]0;` if it contains a backslash, and may itself reference other aliases:
+//!
+//! ```text
+//! OSC=\e]
+//! TITLE_END=$OSC\x30
+//! ```
+//!
+//! Aliases are substituted in the raw command line before clap parses it, so that
+//! `--keymap` values keep their usual parser.
+
+use anyhow::{bail, Context, Result};
+use clap::{Arg, Command};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Deepest nesting of aliases referencing aliases, beyond which a cycle is assumed.
+const MAX_DEPTH: usize = 10;
+
+/// Where the value of an option is on the command line.
+struct OptionValue {
+    /// ID of the option
+    id: String,
+    /// Index of the argument holding the value
+    index: usize,
+    /// Byte offset of the value in that argument, for `--option=value` and `-ovalue`
+    start: usize,
+}
+
+/// Substitutes the aliases of `--keymap-alias-file` in the `--keymap` values of `args`.
+///
+/// # Arguments
+/// * `command` - The clap command `args` are meant for
+/// * `args` - The command line, starting with the program name
+///
+/// # Returns
+/// * `Ok(args)` with every `$NAME` in keymaps replaced by the hex of its alias,
+///   unchanged without `--keymap-alias-file`
+/// * `Err(error)` if the file cannot be read or references an unknown alias
+pub fn expand_args(command: &Command, mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let values = option_values(command, &args);
+    let Some(file) = values.iter().rfind(|value| value.id == "keymap_alias_file") else {
+        return Ok(args);
+    };
+    let path = PathBuf::from(&value_of(&args, file));
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("cannot read keymap alias file '{}'", path.display()))?;
+    let definitions = parse_definitions(&text)
+        .with_context(|| format!("invalid keymap alias file '{}'", path.display()))?;
+
+    for value in values.iter().filter(|value| value.id == "keymaps") {
+        let keymap = value_of(&args, value);
+        let expanded = substitute(
+            &keymap,
+            |name| resolve(name, &definitions, 1),
+            |text| Ok(text.to_string()),
+        )?;
+        let mut arg = args[value.index].to_string_lossy()[..value.start].to_string();
+        arg.push_str(&expanded);
+        args[value.index] = arg.into();
+    }
+
+    Ok(args)
+}
+
+/// Returns the value of an option as a string, see [`option_values`].
+fn value_of(args: &[OsString], value: &OptionValue) -> String {
+    args[value.index].to_string_lossy()[value.start..].to_string()
+}
+
+/// Whether an option takes a value, like `--keymap` but unlike `--epoll`.
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+/// Finds the values of the options on a command line, as clap would.
+///
+/// Options end at `--` or the first positional argument, the command to run, so that
+/// the arguments of the command are left alone. Options unknown to `command` are
+/// skipped, leaving their errors to clap.
+fn option_values(command: &Command, args: &[OsString]) -> Vec<OptionValue> {
+    let mut values = Vec::new();
+    let mut i = 1;
+
+    while let Some(token) = args.get(i).and_then(|arg| arg.to_str()) {
+        i += 1;
+        if token == "--" || token == "-" || !token.starts_with('-') {
+            break;
+        }
+
+        let (arg, start) = if let Some(long) = token.strip_prefix("--") {
+            let name = long.split('=').next().unwrap_or_default();
+            let Some(arg) = command.get_arguments().find(|arg| {
+                arg.get_long() == Some(name)
+                    || arg
+                        .get_all_aliases()
+                        .is_some_and(|aliases| aliases.contains(&name))
+            }) else {
+                continue;
+            };
+            (arg, (long.len() > name.len()).then_some(name.len() + 3))
+        } else {
+            // In a cluster of short options, the first one taking a value ends it
+            let Some((offset, arg)) = token.char_indices().skip(1).find_map(|(offset, c)| {
+                command
+                    .get_arguments()
+                    .find(|arg| arg.get_short() == Some(c) && takes_value(arg))
+                    .map(|arg| (offset + c.len_utf8(), arg))
+            }) else {
+                continue;
+            };
+            let start = offset + usize::from(token[offset..].starts_with('='));
+            (arg, (start < token.len()).then_some(start))
+        };
+        if !takes_value(arg) {
+            continue;
+        }
+
+        let id = arg.get_id().to_string();
+        match start {
+            Some(start) => values.push(OptionValue {
+                id,
+                index: i - 1,
+                start,
+            }),
+            None if i < args.len() => {
+                values.push(OptionValue {
+                    id,
+                    index: i,
+                    start: 0,
+                });
+                i += 1;
+            }
+            None => {}
+        }
+    }
+
+    values
+}
+
+/// Parses the `NAME=VALUE` lines of an alias file, without resolving the values.
+fn parse_definitions(text: &str) -> Result<HashMap<String, String>> {
+    let mut definitions = HashMap::new();
+
+    for (number, line) in (1..).zip(text.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            bail!("line {number}: expected NAME=VALUE");
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.bytes().all(is_name_byte) {
+            bail!("line {number}: invalid alias name '{name}'");
+        }
+        definitions.insert(name.to_string(), value.trim().to_string());
+    }
+
+    Ok(definitions)
+}
+
+/// Whether a byte may be part of an alias name.
+fn is_name_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Returns the hex of an alias, resolving the aliases it references.
+///
+/// # Arguments
+/// * `name` - Name of the alias, without `$`
+/// * `definitions` - Definitions of the alias file
+/// * `depth` - Nesting level of the alias, from 1
+fn resolve(name: &str, definitions: &HashMap<String, String>, depth: usize) -> Result<String> {
+    if depth > MAX_DEPTH {
+        bail!(
+            "alias '${name}' is nested more than {MAX_DEPTH} levels deep, is it part of a cycle?"
+        );
+    }
+    let Some(value) = definitions.get(name) else {
+        bail!("unknown keymap alias '${name}'");
+    };
+
+    // The text around references is hex, or escaped if the value has a backslash
+    let escaped = value.contains('\\');
+    substitute(
+        value,
+        |name| resolve(name, definitions, depth + 1),
+        |text| {
+            if !escaped {
+                return Ok(text.to_string());
+            }
+            let bytes = unescape(text)?;
+            Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+        },
+    )
+}
+
+/// Replaces every `$NAME` in `text` by `reference(NAME)`, and the text around by `literal(text)`.
+fn substitute(
+    text: &str,
+    mut reference: impl FnMut(&str) -> Result<String>,
+    mut literal: impl FnMut(&str) -> Result<String>,
+) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(dollar) = rest.find('$') {
+        let name_length = rest[dollar + 1..]
+            .bytes()
+            .take_while(|&b| is_name_byte(b))
+            .count();
+        if name_length == 0 {
+            bail!("'$' without an alias name in '{text}'");
+        }
+        result.push_str(&literal(&rest[..dollar])?);
+        result.push_str(&reference(&rest[dollar + 1..dollar + 1 + name_length])?);
+        rest = &rest[dollar + 1 + name_length..];
+    }
+    result.push_str(&literal(rest)?);
+
+    Ok(result)
+}
+
+/// Decodes an escaped string: `\e`, `\xHH`, `\n`, `\r`, `\t`, `\a`, `\0` and `\\`.
+fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('e') => 0x1b,
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('a') => 0x07,
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .with_context(|| format!("invalid escape '\\x{hex}' in '{text}'"))?
+            }
+            Some(other) => bail!("unknown escape '\\{other}' in '{text}'"),
+            None => bail!("trailing backslash in '{text}'"),
+        });
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_resolve_recursively_until_a_cycle() {
+        let definitions = parse_definitions(
+            "# OSC sequences\nOSC=\\e]\nTITLE_END = $OSC\\x30\nCTRL_C=03\nA=$B\nB=$A\n",
+        )
+        .unwrap();
+        assert_eq!(resolve("TITLE_END", &definitions, 1).unwrap(), "1b5d30");
+        assert_eq!(resolve("CTRL_C", &definitions, 1).unwrap(), "03");
+        assert!(resolve("A", &definitions, 1).is_err());
+        assert!(resolve("MISSING", &definitions, 1).is_err());
+        assert!(parse_definitions("no equals sign").is_err());
+    }
+
+    #[test]
+    fn only_keymap_values_before_the_command_are_found() {
+        let command = Command::new("test")
+            .arg(Arg::new("keymaps").short('k').long("keymap"))
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .arg(Arg::new("command"));
+        let args: Vec<OsString> = ["test", "-k", "A", "--keymap=B", "-vkC", "vim", "-k", "D"]
+            .map(OsString::from)
+            .to_vec();
+        let values: Vec<String> = option_values(&command, &args)
+            .iter()
+            .map(|value| value_of(&args, value))
+            .collect();
+        assert_eq!(values, ["A", "B", "C"]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Locating the PTY master of an already-running process for `--attach`.
+//!
+//! The attached process only holds the slave side (`/dev/pts/N`). The master side is owned
+//! by whichever program allocated the PTY (a terminal emulator, `sshd`, ...), so it is looked
+//! up through `/proc/<pid>/fdinfo` and duplicated into this process with `pidfd_getfd(2)`.
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::Pid;
+use std::fs;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+/// Finds the PTY slave used by `pid` and returns a duplicate of the corresponding master fd.
+///
+/// # Arguments
+/// * `pid` - Process ID of the running process to attach to
+pub fn open_master(pid: Pid) -> Result<OwnedFd> {
+    let tty_index = find_slave_index(pid)?;
+    let (holder, fd) = find_master_holder(tty_index)?
+        .with_context(|| format!("no process holds the master side of /dev/pts/{tty_index}"))?;
+
+    duplicate_fd(holder, fd)
+        .with_context(|| format!("failed to take master fd {fd} from process {holder}"))
+}
+
+/// Returns `N` for the first `/dev/pts/N` among the open fds of `pid`.
+fn find_slave_index(pid: Pid) -> Result<u32> {
+    let fd_dir = PathBuf::from(format!("/proc/{pid}/fd"));
+    let entries =
+        fs::read_dir(&fd_dir).with_context(|| format!("cannot read {}", fd_dir.display()))?;
+
+    for entry in entries.flatten() {
+        let Ok(target) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        if let Some(index) = target
+            .strip_prefix("/dev/pts")
+            .ok()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse().ok())
+        {
+            return Ok(index);
+        }
+    }
+
+    bail!("process {pid} has no PTY slave open")
+}
+
+/// Scans every process for an open `/dev/ptmx` whose `tty-index` is `tty_index`.
+fn find_master_holder(tty_index: u32) -> Result<Option<(Pid, i32)>> {
+    for proc_entry in fs::read_dir("/proc")?.flatten() {
+        let Some(holder) = proc_entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+
+        // Processes of other users are unreadable; skip them silently
+        let Ok(fds) = fs::read_dir(proc_entry.path().join("fd")) else {
+            continue;
+        };
+
+        for fd_entry in fds.flatten() {
+            let Ok(target) = fs::read_link(fd_entry.path()) else {
+                continue;
+            };
+            if target != Path::new("/dev/ptmx") && target != Path::new("/dev/pts/ptmx") {
+                continue;
+            }
+
+            let Some(fd) = fd_entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            else {
+                continue;
+            };
+            let fdinfo = proc_entry.path().join("fdinfo").join(fd_entry.file_name());
+            if read_tty_index(&fdinfo) == Some(tty_index) {
+                return Ok(Some((Pid::from_raw(holder), fd)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `tty-index` field that Linux reports for `/dev/ptmx` fds.
+fn read_tty_index(fdinfo: &Path) -> Option<u32> {
+    fs::read_to_string(fdinfo)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("tty-index:"))
+        .and_then(|index| index.trim().parse().ok())
+}
+
+/// Duplicates `fd` of process `pid` into this process.
+fn duplicate_fd(pid: Pid, fd: i32) -> Result<OwnedFd> {
+    // SAFETY: plain syscalls; the returned fds are owned by this process.
+    unsafe {
+        let pidfd = nix::libc::syscall(nix::libc::SYS_pidfd_open, pid.as_raw(), 0);
+        if pidfd < 0 {
+            return Err(nix::Error::last().into());
+        }
+        let pidfd = OwnedFd::from_raw_fd(pidfd as i32);
+
+        let dupfd = nix::libc::syscall(
+            nix::libc::SYS_pidfd_getfd,
+            std::os::fd::AsRawFd::as_raw_fd(&pidfd),
+            fd,
+            0,
+        );
+        if dupfd < 0 {
+            return Err(nix::Error::last().into());
+        }
+        Ok(OwnedFd::from_raw_fd(dupfd as i32))
+    }
+}
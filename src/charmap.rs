@@ -0,0 +1,181 @@
+//! `--input-char-map` and `--output-char-map`: replacing single Unicode characters in a stream.
+
+use crate::processor::Processor;
+use std::collections::HashMap;
+
+/// Parses a character mapping in the format "from_char:to_char".
+///
+/// # Arguments
+/// * `s` - Two characters separated by ':', either of which may itself be ':'
+///
+/// # Returns
+/// * `Ok((from, to))` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_char_map(s: &str) -> Result<(char, char), String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(from), Some(':'), Some(to), None) => Ok((from, to)),
+        _ => Err(format!(
+            "invalid character map format '{s}', expected format 'from_char:to_char'"
+        )),
+    }
+}
+
+/// Where the stream is relative to an ANSI escape sequence.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    /// Plain text, subject to the map.
+    None,
+    /// After ESC and any intermediate bytes.
+    Esc,
+    /// Inside a control sequence (`ESC [`), up to its final byte.
+    Csi,
+    /// Inside a control string (OSC, DCS, ...), up to BEL or ST.
+    String,
+    /// After ESC inside a control string, which may start ST (`ESC \`).
+    StringEsc,
+}
+
+impl Escape {
+    /// Returns the state after `byte`, which is part of the sequence.
+    fn next(self, byte: u8) -> Self {
+        match (self, byte) {
+            (Escape::Esc, 0x20..=0x2f) => Escape::Esc,
+            (Escape::Esc, b'[') => Escape::Csi,
+            (Escape::Esc, b']' | b'P' | b'X' | b'^' | b'_') => Escape::String,
+            (Escape::Csi, 0x40..=0x7e) => Escape::None,
+            (Escape::Csi, _) => Escape::Csi,
+            (Escape::String, 0x07) => Escape::None,
+            (Escape::String, 0x1b) => Escape::StringEsc,
+            (Escape::String, _) => Escape::String,
+            (Escape::StringEsc, b'\\') => Escape::None,
+            (Escape::StringEsc, _) => Escape::String,
+            _ => Escape::None,
+        }
+    }
+}
+
+/// Replaces characters of UTF-8 text, leaving escape sequences and invalid bytes alone.
+pub struct CharMapProcessor {
+    map: HashMap<char, char>,
+    /// Whether an escape sequence may go on in the next chunk
+    split_escapes: bool,
+    escape: Escape,
+    incomplete: Vec<u8>,
+}
+
+impl CharMapProcessor {
+    /// Creates a processor for input or output.
+    ///
+    /// # Arguments
+    /// * `map` - Replacement of each mapped character
+    /// * `split_escapes` - Whether escape sequences may be split across chunks, as in
+    ///   output; typed sequences arrive in one read, and a bare Esc key ends its own
+    pub fn new(map: HashMap<char, char>, split_escapes: bool) -> Self {
+        Self {
+            map,
+            split_escapes,
+            escape: Escape::None,
+            incomplete: Vec::new(),
+        }
+    }
+}
+
+impl Processor for CharMapProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.incomplete);
+        data.extend_from_slice(input);
+
+        let mut output = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let byte = data[i];
+            if self.escape != Escape::None {
+                self.escape = self.escape.next(byte);
+                output.push(byte);
+                i += 1;
+                continue;
+            }
+            if byte == 0x1b {
+                self.escape = Escape::Esc;
+                output.push(byte);
+                i += 1;
+                continue;
+            }
+
+            let len = match byte {
+                0xc2..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf4 => 4,
+                _ => 1,
+            };
+            if i + len > data.len() {
+                // A truncated character at the end may be completed by the next chunk
+                self.incomplete = data[i..].to_vec();
+                break;
+            }
+
+            match std::str::from_utf8(&data[i..i + len])
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => {
+                    let c = self.map.get(&c).copied().unwrap_or(c);
+                    output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    i += len;
+                }
+                None => {
+                    output.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        if !self.split_escapes {
+            self.escape = Escape::None;
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processor(map: &[(char, char)]) -> CharMapProcessor {
+        CharMapProcessor::new(map.iter().copied().collect(), true)
+    }
+
+    #[test]
+    fn escape_sequences_are_left_alone() {
+        let mut charmap = processor(&[('m', 'M'), ('B', 'b'), ('0', 'O')]);
+        assert_eq!(
+            charmap.process(b"m\x1b[0m\x1b(B\x1b]0;m\x07m0"),
+            b"M\x1b[0m\x1b(B\x1b]0;m\x07MO"
+        );
+    }
+
+    #[test]
+    fn escape_sequences_end_with_their_input_chunk() {
+        for (split_escapes, mapped) in [(false, &b"A"[..]), (true, b"a")] {
+            let mut charmap = CharMapProcessor::new(HashMap::from([('a', 'A')]), split_escapes);
+            assert_eq!(charmap.process(&[0x1b]), [0x1b]);
+            assert_eq!(charmap.process(b"a"), mapped);
+        }
+    }
+
+    #[test]
+    fn characters_split_across_chunks_are_mapped() {
+        let mut charmap = processor(&[('€', '$')]);
+        let euro = "€".as_bytes();
+        assert_eq!(charmap.process(&[b'a', euro[0], euro[1]]), b"a");
+        assert_eq!(charmap.process(&euro[2..]), b"$");
+    }
+
+    #[test]
+    fn char_map_accepts_colons() {
+        assert_eq!(parse_char_map("é:e"), Ok(('é', 'e')));
+        assert_eq!(parse_char_map(":::"), Ok((':', ':')));
+        assert!(parse_char_map("ab:c").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! `--input-from-clipboard` and `--output-to-clipboard`: talking to the system clipboard.
+//!
+//! There is no portable clipboard API for terminal programs, so the usual command line
+//! tools are run: `wl-paste`/`wl-copy` under Wayland, `xclip` under X11 and
+//! `pbpaste`/`pbcopy` on macOS.
+
+use crate::processor::Processor;
+use anyhow::{bail, Context, Result};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Returns the command reading the clipboard and the one writing it, with their arguments.
+fn tools() -> (&'static [&'static str], &'static [&'static str]) {
+    if cfg!(target_os = "macos") {
+        (&["pbpaste"], &["pbcopy"])
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        (&["wl-paste", "--no-newline"], &["wl-copy"])
+    } else {
+        (
+            &["xclip", "-selection", "clipboard", "-out"],
+            &["xclip", "-selection", "clipboard", "-in"],
+        )
+    }
+}
+
+/// Reads the current content of the clipboard.
+pub fn paste() -> Result<Vec<u8>> {
+    let (paste, _) = tools();
+    let output = Command::new(paste[0])
+        .args(&paste[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("failed to run '{}' to read the clipboard", paste[0]))?;
+    if !output.status.success() {
+        bail!(
+            "'{}' failed to read the clipboard ({})",
+            paste[0],
+            output.status
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// Replaces the content of the clipboard.
+///
+/// # Arguments
+/// * `bytes` - New content of the clipboard
+pub fn copy(bytes: &[u8]) -> Result<()> {
+    let (_, copy) = tools();
+    let mut child = Command::new(copy[0])
+        .args(&copy[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to run '{}' to write the clipboard", copy[0]))?;
+    // Closing stdin when the write is done lets the tool take over the selection
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(bytes)?;
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("'{}' failed to write the clipboard ({status})", copy[0]);
+    }
+    Ok(())
+}
+
+/// The output captured so far, shared with whoever copies it at the end of the session.
+#[derive(Clone, Default)]
+pub struct Capture(Arc<Mutex<VecDeque<u8>>>);
+
+impl Capture {
+    /// Returns the captured bytes, leaving the capture empty.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap()).into()
+    }
+}
+
+/// Appends every chunk to a [`Capture`] while passing it through unchanged.
+///
+/// Only the last `max_bytes` bytes are kept, like the scrollback of a terminal.
+pub struct CaptureProcessor {
+    capture: Capture,
+    max_bytes: usize,
+}
+
+impl CaptureProcessor {
+    pub fn new(capture: Capture, max_bytes: usize) -> Self {
+        Self { capture, max_bytes }
+    }
+}
+
+impl Processor for CaptureProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut captured = self.capture.0.lock().unwrap();
+        captured.extend(input);
+        let excess = captured.len().saturating_sub(self.max_bytes);
+        captured.drain(..excess);
+
+        input.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_keeps_the_last_bytes() {
+        let capture = Capture::default();
+        let mut processor = CaptureProcessor::new(capture.clone(), 4);
+        assert_eq!(processor.process(b"abc"), b"abc");
+        assert_eq!(processor.process(b"def"), b"def");
+        assert_eq!(capture.take(), b"cdef");
+        assert_eq!(capture.take(), b"");
+    }
+}
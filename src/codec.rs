@@ -0,0 +1,107 @@
+//! `--input-codec` and `--output-codec`: how bytes are interpreted before further processing.
+
+use crate::processor::Processor;
+
+/// Encoding of one direction of the stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Codec {
+    /// Validate as UTF-8, replacing invalid sequences with U+FFFD.
+    Utf8,
+    /// Transcode ISO-8859-1 to UTF-8.
+    Latin1,
+    /// Pass bytes through without interpretation.
+    Binary,
+}
+
+impl Codec {
+    /// Returns the processor implementing the codec, if it transforms anything.
+    pub fn processor(self) -> Option<Box<dyn Processor>> {
+        match self {
+            Codec::Utf8 => Some(Box::new(Utf8Validator::default())),
+            Codec::Latin1 => Some(Box::new(Latin1Decoder)),
+            Codec::Binary => None,
+        }
+    }
+}
+
+/// Replaces invalid UTF-8 with U+FFFD, holding back a sequence split across chunks.
+#[derive(Default)]
+pub struct Utf8Validator {
+    incomplete: Vec<u8>,
+}
+
+impl Processor for Utf8Validator {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.incomplete);
+        data.extend_from_slice(input);
+
+        let mut output = Vec::with_capacity(data.len());
+        let mut rest = &data[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    output.extend_from_slice(valid.as_bytes());
+                    break;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    output.extend_from_slice(valid);
+                    match e.error_len() {
+                        Some(len) => {
+                            output.extend_from_slice(
+                                char::REPLACEMENT_CHARACTER
+                                    .encode_utf8(&mut [0; 4])
+                                    .as_bytes(),
+                            );
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            // A truncated sequence at the end may be completed by the next chunk
+                            self.incomplete = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+/// Interprets every byte as the ISO-8859-1 character of the same code point.
+pub struct Latin1Decoder;
+
+impl Processor for Latin1Decoder {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        input
+            .iter()
+            .map(|&byte| char::from(byte))
+            .collect::<String>()
+            .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_replaces_invalid_bytes() {
+        let mut codec = Utf8Validator::default();
+        assert_eq!(codec.process(b"a\xffb"), "a\u{fffd}b".as_bytes());
+    }
+
+    #[test]
+    fn utf8_keeps_sequences_split_across_chunks() {
+        let mut codec = Utf8Validator::default();
+        let euro = "€".as_bytes();
+        assert_eq!(codec.process(&[b'a', euro[0]]), b"a");
+        assert_eq!(codec.process(&euro[1..]), euro);
+    }
+
+    #[test]
+    fn latin1_is_transcoded_to_utf8() {
+        assert_eq!(Latin1Decoder.process(b"caf\xe9\x1b"), "café\x1b".as_bytes());
+    }
+}
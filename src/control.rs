@@ -0,0 +1,126 @@
+//! The `--control-socket` listener and the `send` sub-command talking to it.
+//!
+//! The protocol is deliberately minimal: a client connects, writes the bytes to
+//! inject and closes the connection. The bytes are then handled like user input.
+
+use crate::inject;
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A bound control socket, whose path is removed again when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Binds the socket and spawns a thread forwarding received bytes to the event loop.
+    ///
+    /// A socket left at `path` by a wrapper that did not exit cleanly is replaced, but
+    /// not one that a running wrapper still accepts connections on. Each client is read
+    /// on its own thread, so that a client that never closes its connection only holds
+    /// up its own bytes.
+    ///
+    /// # Arguments
+    /// * `path` - Filesystem path of the Unix domain socket
+    /// * `injector` - Write end of the pipe read by the event loop, owned by the threads
+    pub fn bind(path: &Path, injector: OwnedFd) -> Result<Self> {
+        remove_stale(path)?;
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to bind control socket '{}'", path.display()))?;
+
+        // Keeps the bytes of each client together in the pipe
+        let injector = Arc::new(Mutex::new(injector));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let injector = Arc::clone(&injector);
+                thread::spawn(move || {
+                    let mut received = Vec::new();
+                    if stream.read_to_end(&mut received).is_err() {
+                        return;
+                    }
+                    let injector = injector.lock().unwrap_or_else(|e| e.into_inner());
+                    let _ = inject(&*injector, &received);
+                });
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+/// Removes a socket file nothing listens on anymore, failing if a listener answers.
+fn remove_stale(path: &Path) -> Result<()> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        // Left for `bind` to report
+        return Ok(());
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => bail!(
+            "control socket '{}' is in use by another running wrapper",
+            path.display()
+        ),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)
+            .with_context(|| format!("cannot remove stale control socket '{}'", path.display())),
+        Err(_) => Ok(()),
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends bytes to a running wrapper through its control socket.
+///
+/// # Arguments
+/// * `path` - Filesystem path of the Unix domain socket
+/// * `bytes` - Bytes to inject as input
+pub fn send(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to control socket '{}'", path.display()))?;
+    stream.write_all(bytes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_sockets_are_replaced_and_idle_clients_block_no_one() {
+        let path = std::env::temp_dir().join(format!("cli-keyhook-control-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // A listener closed without removing its path, as after a crash
+        drop(UnixListener::bind(&path).unwrap());
+
+        let (injected, injector) = nix::unistd::pipe().unwrap();
+        let socket = ControlSocket::bind(&path, injector).unwrap();
+        assert!(ControlSocket::bind(&path, nix::unistd::pipe().unwrap().1).is_err());
+
+        let _idle = UnixStream::connect(&path).unwrap();
+        send(&path, b"ls\r").unwrap();
+        let mut received = [0; 3];
+        std::fs::File::from(injected)
+            .read_exact(&mut received)
+            .unwrap();
+        assert_eq!(&received, b"ls\r");
+        drop(socket);
+    }
+}
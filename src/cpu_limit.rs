@@ -0,0 +1,162 @@
+//! `--child-cpu-limit`: limiting the share of CPU time the child may use.
+//!
+//! The limit is enforced in one of two ways, which differ once it is reached:
+//! * With `--child-cpu-cgroup`, the child joins a cgroup v2 whose `cpu.max` is set to
+//!   the limit. The kernel throttles it: the command keeps running, only slower.
+//! * Otherwise, the `RLIMIT_CPU` of the child is raised every second to the CPU time
+//!   the limit allows since it started. Once it has used more, the kernel sends it
+//!   SIGXCPU, which terminates it.
+
+use anyhow::{Context, Result};
+use nix::unistd::Pid;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Period of the `cpu.max` quota, the kernel's default.
+const CPU_MAX_PERIOD_US: u64 = 100_000;
+
+/// How often the `RLIMIT_CPU` of the child is raised.
+const BUDGET_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sets the `cpu.max` of a cgroup v2 to a percentage of one core.
+///
+/// # Arguments
+/// * `cgroup` - Directory of the cgroup, like `/sys/fs/cgroup/user.slice/editor`
+/// * `percent` - Share of one core, above 100 for several cores
+pub fn set_cpu_max(cgroup: &Path, percent: u32) -> Result<()> {
+    let quota = u64::from(percent) * CPU_MAX_PERIOD_US / 100;
+    let path = cgroup.join("cpu.max");
+    std::fs::write(&path, format!("{quota} {CPU_MAX_PERIOD_US}\n"))
+        .with_context(|| format!("cannot write '{}'", path.display()))
+}
+
+/// Opens the `cgroup.procs` of a cgroup v2 before forking, see [`join_cgroup`].
+///
+/// # Arguments
+/// * `cgroup` - Directory of the cgroup
+pub fn open_cgroup(cgroup: &Path) -> Result<File> {
+    let path = cgroup.join("cgroup.procs");
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("cannot open '{}'", path.display()))
+}
+
+/// Moves the calling process into a cgroup v2, from the child before `execvp`.
+///
+/// Writing `0` stands for the writing process, so nothing is formatted or allocated.
+///
+/// # Arguments
+/// * `procs` - `cgroup.procs` of the cgroup, opened with [`open_cgroup`]
+pub fn join_cgroup(mut procs: &File) -> Result<()> {
+    procs
+        .write_all(b"0\n")
+        .context("cannot join the cgroup of --child-cpu-cgroup")
+}
+
+/// A thread raising the `RLIMIT_CPU` of the child, which stops when dropped.
+pub struct CpuBudget {
+    stopped: Arc<AtomicBool>,
+}
+
+impl CpuBudget {
+    /// Spawns the thread keeping the soft `RLIMIT_CPU` of `pid` at the CPU time that
+    /// `percent` allows since now.
+    ///
+    /// The budget is averaged over the whole session, so an idle command saves up
+    /// time for later bursts. The hard limit is left alone, as it cannot be raised
+    /// again without privileges.
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID of the child
+    /// * `percent` - Share of one core, above 100 for several cores
+    pub fn spawn(pid: Pid, percent: u32) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&stopped);
+        let start = Instant::now();
+
+        thread::spawn(move || {
+            // Once dropped, the child may be reaped and its PID reused by another process
+            while !stop.load(Ordering::Acquire) {
+                let seconds = allowed_seconds(start.elapsed(), percent);
+                if let Err(e) = raise_cpu_rlimit(pid, seconds) {
+                    if e.raw_os_error() != Some(nix::libc::ESRCH) {
+                        eprintln!(
+                            "cli-keyhook: warning: cannot set RLIMIT_CPU of the command ({e})"
+                        );
+                    }
+                    break;
+                }
+                thread::sleep(BUDGET_INTERVAL);
+            }
+        });
+
+        Self { stopped }
+    }
+}
+
+impl Drop for CpuBudget {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// Returns the CPU seconds allowed after `elapsed`, rounded up with two seconds of slack:
+/// the limit counts whole seconds and is only raised once per [`BUDGET_INTERVAL`].
+fn allowed_seconds(elapsed: Duration, percent: u32) -> u64 {
+    let allowed_ms = elapsed.as_millis() as u64 * u64::from(percent) / 100;
+    allowed_ms.div_ceil(1000) + 2
+}
+
+/// Sets the soft `RLIMIT_CPU` of another process, capped at its hard limit.
+fn raise_cpu_rlimit(pid: Pid, seconds: u64) -> io::Result<()> {
+    let mut limit = nix::libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `prlimit` only reads the current limits into `limit` when the new one is null.
+    if unsafe {
+        nix::libc::prlimit(
+            pid.as_raw(),
+            nix::libc::RLIMIT_CPU,
+            std::ptr::null(),
+            &mut limit,
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    limit.rlim_cur = seconds.min(limit.rlim_max);
+    // SAFETY: `limit` is a valid `rlimit`, and the old limits are not requested.
+    if unsafe {
+        nix::libc::prlimit(
+            pid.as_raw(),
+            nix::libc::RLIMIT_CPU,
+            &limit,
+            std::ptr::null_mut(),
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_grows_with_the_limit() {
+        assert_eq!(allowed_seconds(Duration::ZERO, 50), 2);
+        assert_eq!(allowed_seconds(Duration::from_millis(1500), 50), 3);
+        assert_eq!(allowed_seconds(Duration::from_secs(10), 50), 7);
+        assert_eq!(allowed_seconds(Duration::from_secs(10), 200), 22);
+    }
+}
@@ -0,0 +1,173 @@
+//! `--debug-ptrace`: logging what the child writes to stdout and stderr, as seen by
+//! the kernel rather than read back from the PTY.
+//!
+//! The child is traced with `ptrace` from a dedicated thread, as every request must
+//! come from the thread that attached. Only the process that was forked is traced:
+//! writes from its threads and its own children are not logged.
+//!
+//! So that its first writes are not missed, the child waits before `execvp` until
+//! the tracer has attached and closes the write end of a pipe, the gate.
+
+use anyhow::Result;
+use nix::sys::ptrace::{self, Options};
+use nix::sys::signal::Signal;
+use nix::sys::uio::{process_vm_readv, RemoteIoVec};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::io::{self, IoSliceMut, Write};
+use std::os::fd::OwnedFd;
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Whether the traced child has exited and been reaped by the tracer thread.
+///
+/// Only the tracer may wait for the child, since a `waitpid` from any other thread
+/// would also consume the stops meant for it.
+static EXITED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+/// Creates the gate the child waits on, returning its read and write ends.
+pub fn gate() -> Result<(OwnedFd, OwnedFd)> {
+    Ok(nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?)
+}
+
+/// Waits in the child until the tracer has attached, or given up.
+///
+/// # Arguments
+/// * `gate` - Read end of the gate; the child must have closed the write end
+pub fn wait_for_tracer(gate: OwnedFd) {
+    let mut byte = [0];
+    while matches!(
+        nix::unistd::read(&gate, &mut byte),
+        Err(nix::errno::Errno::EINTR)
+    ) {}
+}
+
+/// Attaches to the child from a new thread, which then logs its writes until it exits.
+///
+/// # Arguments
+/// * `pid` - Process ID of the child
+/// * `gate` - Write end of the gate, closed once the child is traced
+///
+/// # Returns
+/// * `Ok(())` once attached; from then on, the child must only be waited for with
+///   [`has_exited`] and [`wait`]
+/// * `Err(error)` if the child cannot be traced, in which case it is left alone
+pub fn trace_writes(pid: Pid, gate: OwnedFd) -> Result<()> {
+    let (attached, result) = mpsc::channel();
+
+    thread::spawn(move || {
+        if let Err(e) = ptrace::attach(pid) {
+            let _ = attached.send(Err(e));
+            return;
+        }
+        let _ = attached.send(Ok(()));
+
+        if let Err(e) = trace_loop(pid, gate) {
+            eprintln!("cli-keyhook: warning: stopped tracing the command ({e})");
+            let _ = ptrace::detach(pid, None);
+            reap(pid);
+        }
+        let (exited, exit) = &EXITED;
+        *exited.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        exit.notify_all();
+    });
+
+    result
+        .recv()?
+        .map_err(|e| anyhow::anyhow!("cannot trace the command ({e})"))
+}
+
+/// Checks without blocking whether the traced child has exited.
+pub fn has_exited() -> bool {
+    *EXITED.0.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Waits for the traced child to exit.
+pub fn wait() {
+    let (exited, exit) = &EXITED;
+    let guard = exited.lock().unwrap_or_else(|e| e.into_inner());
+    drop(exit.wait_while(guard, |exited| !*exited));
+}
+
+/// Resumes the child until its next system call, logging returns from writes to
+/// stdout and stderr, until it exits.
+fn trace_loop(pid: Pid, gate: OwnedFd) -> nix::Result<()> {
+    let mut gate = Some(gate);
+    loop {
+        match waitpid(pid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => return Ok(()),
+            // The SIGSTOP sent by `attach`, which is not delivered
+            WaitStatus::Stopped(_, Signal::SIGSTOP) if gate.is_some() => {
+                ptrace::setoptions(
+                    pid,
+                    Options::PTRACE_O_TRACESYSGOOD
+                        | Options::PTRACE_O_TRACEEXEC
+                        | Options::PTRACE_O_EXITKILL,
+                )?;
+                // Lets the child go on to `execvp`
+                gate = None;
+            }
+            WaitStatus::Stopped(_, signal) => {
+                ptrace::syscall(pid, signal)?;
+                continue;
+            }
+            WaitStatus::PtraceSyscall(_) => log_write(pid)?,
+            _ => {}
+        }
+        ptrace::syscall(pid, None)?;
+    }
+}
+
+/// Logs the data of a `write` to fd 1 or 2 when the child stops on its return.
+///
+/// Only the bytes the kernel reports as written are logged. On entry to a system
+/// call, its return value register holds `-ENOSYS`, so entries are skipped.
+fn log_write(pid: Pid) -> nix::Result<()> {
+    let regs = ptrace::getregs(pid)?;
+    let written = regs.rax as i64;
+    if regs.orig_rax != nix::libc::SYS_write as u64 || !matches!(regs.rdi, 1 | 2) || written <= 0 {
+        return Ok(());
+    }
+
+    let mut data = vec![0; written as usize];
+    let remote = RemoteIoVec {
+        base: regs.rsi as usize,
+        len: data.len(),
+    };
+    let read = process_vm_readv(pid, &mut [IoSliceMut::new(&mut data)], &[remote])?;
+    data.truncate(read);
+
+    let _ = io::stderr().write_all(trace_line(regs.rdi, &data).as_bytes());
+    Ok(())
+}
+
+/// Formats a logged write, ending with CR LF as the terminal is in raw mode.
+fn trace_line(fd: u64, data: &[u8]) -> String {
+    format!(
+        "cli-keyhook: trace: write({fd}, \"{}\")\r\n",
+        data.escape_ascii()
+    )
+}
+
+/// Waits for the child to exit after it is no longer traced.
+fn reap(pid: Pid) {
+    while let Ok(status) = waitpid(pid, Some(WaitPidFlag::__WALL)) {
+        if matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..)) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_are_logged_escaped() {
+        assert_eq!(
+            trace_line(1, b"ls\r\n\x1b[0m\"x\""),
+            "cli-keyhook: trace: write(1, \"ls\\r\\n\\x1b[0m\\\"x\\\"\")\r\n"
+        );
+    }
+}
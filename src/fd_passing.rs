@@ -0,0 +1,53 @@
+//! `--fd-passing`: receiving an additional input fd from another process.
+
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+use std::io::IoSliceMut;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Connects to a Unix domain socket and receives one fd sent with `SCM_RIGHTS`.
+///
+/// # Arguments
+/// * `path` - Filesystem path of the socket the fd is offered on
+pub fn receive_fd(path: &Path) -> Result<OwnedFd> {
+    let stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "failed to connect to fd passing socket '{}'",
+            path.display()
+        )
+    })?;
+
+    let mut data = [0u8; 1];
+    let mut iov = [IoSliceMut::new(&mut data)];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1]);
+    let msg = recvmsg::<()>(
+        stream.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buffer),
+        MsgFlags::empty(),
+    )
+    .with_context(|| format!("failed to receive an fd on '{}'", path.display()))?;
+
+    // SAFETY: fds received with SCM_RIGHTS are new and owned by this process.
+    let mut fds: Vec<OwnedFd> = msg
+        .cmsgs()?
+        .filter_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => Some(fds),
+            _ => None,
+        })
+        .flatten()
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .collect();
+    if fds.is_empty() {
+        bail!("no fd was sent on '{}'", path.display());
+    }
+    // Any further fds are closed with the vector
+    let fd = fds.swap_remove(0);
+    // Keep the fd away from commands spawned by hooks
+    fcntl(&fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+    Ok(fd)
+}
@@ -0,0 +1,96 @@
+//! `--input-hex-dump-only` and `--output-hex-dump-only`: inspecting the raw bytes of a
+//! stream instead of forwarding them.
+
+use crate::processor::Processor;
+use crate::{restore_terminal_settings, save_terminal_settings, setup_raw_mode};
+use anyhow::Result;
+use std::io::{self, IsTerminal, Read, Write};
+
+/// The byte ending a dump of terminal input when it is read on its own: Ctrl+D.
+const END_OF_DUMP: u8 = 0x04;
+
+/// Formats one read as space-separated pairs of lowercase hex digits.
+///
+/// # Arguments
+/// * `bytes` - Bytes of the read
+pub fn dump_line(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prints a hex dump line to stdout for every read from stdin, without spawning a command.
+///
+/// A terminal on stdin is put in raw mode, so that every key arrives as sent and
+/// Ctrl+C is dumped rather than interrupting; pressing Ctrl+D on its own ends the
+/// dump. Other inputs are dumped until their end.
+///
+/// # Arguments
+/// * `buffer_size` - Size of the buffer for reads from stdin
+pub fn dump_input(buffer_size: usize) -> Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let original_termios = if stdin.is_terminal() {
+        let termios = save_terminal_settings()?;
+        setup_raw_mode()?;
+        Some(termios)
+    } else {
+        None
+    };
+
+    // Raw mode disables output processing, so lines need an explicit carriage return
+    let line_end = if original_termios.is_some() {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    let mut buffer = vec![0u8; buffer_size];
+    let result = loop {
+        let n = match stdin.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e.into()),
+        };
+
+        if let Err(e) = write!(stdout, "{}{line_end}", dump_line(&buffer[..n])) {
+            break Err(e.into());
+        }
+        let _ = stdout.flush();
+        if original_termios.is_some() && buffer[..n] == [END_OF_DUMP] {
+            break Ok(());
+        }
+    };
+
+    if let Some(termios) = original_termios {
+        restore_terminal_settings(&termios)?;
+    }
+    result
+}
+
+/// Replaces every chunk with its hex dump line.
+///
+/// The line ends with CRLF, as the user's terminal is in raw mode while a command runs.
+pub struct HexDumpProcessor;
+
+impl Processor for HexDumpProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        format!("{}\r\n", dump_line(input)).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_become_dump_lines() {
+        assert_eq!(
+            HexDumpProcessor.process(b"a\x1b[0m\r\n"),
+            b"61 1b 5b 30 6d 0d 0a\r\n"
+        );
+    }
+}
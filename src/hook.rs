@@ -0,0 +1,58 @@
+//! Shell commands run in the background in reaction to input.
+
+use crate::hex_encode;
+use crate::processor::Processor;
+use crate::rate::InputRate;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Runs `command` with `sh -c` without waiting for it; a thread reaps it once it exits.
+///
+/// # Arguments
+/// * `command` - Shell command line
+/// * `env` - Additional environment variables of the command
+pub fn spawn_shell(command: &str, env: &[(&str, String)]) {
+    let spawned = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .spawn();
+
+    match spawned {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("cli-keyhook: failed to run '{command}': {e}"),
+    }
+}
+
+/// `--input-event-hook`: runs a command for every chunk of input read from stdin.
+///
+/// The command receives the chunk as `CLI_KEYHOOK_INPUT` (hex format) and, with
+/// `--input-rate-estimator`, the current rate as `CLI_KEYHOOK_INPUT_RATE_BPS`.
+pub struct InputEventHook {
+    command: String,
+    rate: Option<InputRate>,
+}
+
+impl InputEventHook {
+    pub fn new(command: String, rate: Option<InputRate>) -> Self {
+        Self { command, rate }
+    }
+}
+
+impl Processor for InputEventHook {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut env = vec![("CLI_KEYHOOK_INPUT", hex_encode(input))];
+        if let Some(rate) = &self.rate {
+            env.push((
+                "CLI_KEYHOOK_INPUT_RATE_BPS",
+                format!("{:.0}", rate.bytes_per_second()),
+            ));
+        }
+        spawn_shell(&self.command, &env);
+
+        input.to_vec()
+    }
+}
@@ -0,0 +1,14 @@
+//! Importers generating keymaps from the configuration of other programs.
+
+mod fusuma;
+mod keys;
+mod paste_history;
+mod terminfo;
+mod vscode;
+mod xdotool;
+
+pub use self::fusuma::{keymaps_from_fusuma, parse_gesture};
+pub use self::paste_history::keymaps_from_paste_history;
+pub use self::terminfo::keymaps_from_terminfo;
+pub use self::vscode::keymaps_from_vscode;
+pub use self::xdotool::keymaps_from_xdotool_script;
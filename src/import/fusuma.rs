@@ -0,0 +1,246 @@
+//! `--keymap-from-fusuma`: the keystrokes that fusuma's touchpad gestures send.
+//!
+//! Gesture handlers whose command is `xdotool key` are read from fusuma's YAML
+//! configuration; other commands are left out:
+//!
+//! ```yaml
+//! swipe:
+//!   3:
+//!     left:
+//!       command: 'xdotool key alt+Right'
+//! ```
+
+use super::xdotool::{key_sequence, split_words, strip_options};
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Parses a `--fusuma-gesture` in the format "gesture:output_hex".
+///
+/// # Arguments
+/// * `s` - Path of the gesture in the config like `swipe.3.left`, and the bytes it types
+///
+/// # Returns
+/// * `Ok((gesture, output))` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_gesture(s: &str) -> Result<(String, Vec<u8>), String> {
+    let (gesture, output) = s
+        .rsplit_once(':')
+        .filter(|(gesture, _)| !gesture.is_empty())
+        .ok_or_else(|| {
+            format!("invalid gesture format '{s}', expected format 'gesture:output_hex'")
+        })?;
+    let output = match output {
+        "" => Vec::new(),
+        hex => crate::hex_decode(hex).map_err(|e| format!("invalid hex string '{hex}' ({e})"))?,
+    };
+
+    Ok((gesture.to_string(), output))
+}
+
+/// Generates keymaps from the keystrokes of the `xdotool key` gestures of fusuma configs.
+///
+/// Every entry maps the sequence a gesture produces in a terminal to the output given
+/// for it; gestures without an output are left alone.
+///
+/// # Arguments
+/// * `paths` - Paths of the configs, usually `~/.config/fusuma/config.yml`
+/// * `gestures` - Output of each gesture, by their path in the configs
+///
+/// # Returns
+/// * `Ok(keymaps)` on success
+/// * `Err(error)` if a file cannot be read, or a gesture does not send translatable keys
+pub fn keymaps_from_fusuma(
+    paths: &[PathBuf],
+    gestures: &[(String, Vec<u8>)],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut commands = Vec::new();
+    for path in paths {
+        let yaml = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read fusuma config '{}'", path.display()))?;
+        commands.extend(gesture_commands(&yaml));
+    }
+
+    gesture_keymaps(&commands, gestures)
+}
+
+/// Maps the keys sent by each gesture to its output, see [`keymaps_from_fusuma`].
+///
+/// A gesture may have a command in several documents, one per application context,
+/// and each of its sequences is mapped.
+fn gesture_keymaps(
+    commands: &[(String, String)],
+    gestures: &[(String, Vec<u8>)],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut keymaps = Vec::new();
+
+    for (gesture, output) in gestures {
+        let mut sequences = commands
+            .iter()
+            .filter(|(name, _)| name == gesture)
+            .filter_map(|(_, command)| xdotool_keys(command))
+            .peekable();
+        if sequences.peek().is_none() {
+            bail!("fusuma gesture '{gesture}' does not run `xdotool key`");
+        }
+        for sequence in sequences {
+            let sequence =
+                sequence.with_context(|| format!("cannot map fusuma gesture '{gesture}'"))?;
+            keymaps.push((sequence, output.clone()));
+        }
+    }
+
+    Ok(keymaps)
+}
+
+/// Returns the `command` of every gesture, with the path of its keys like `swipe.3.left`.
+///
+/// Only the block mappings fusuma configs are made of are understood: lists, flow
+/// collections and multi-line scalars are skipped. Documents separated by `---`, as
+/// used for application contexts, are all read.
+fn gesture_commands(yaml: &str) -> Vec<(String, String)> {
+    let mut commands = Vec::new();
+    // Indentation and key of the mappings enclosing the current line
+    let mut parents: Vec<(usize, &str)> = Vec::new();
+
+    for line in yaml.lines() {
+        let content = line.trim_start();
+        if content == "---" {
+            parents.clear();
+            continue;
+        }
+        if content.is_empty() || content.starts_with('#') || content.starts_with('-') {
+            continue;
+        }
+        let Some((key, value)) = content
+            .split_once(": ")
+            .or_else(|| content.strip_suffix(':').map(|key| (key, "")))
+        else {
+            continue;
+        };
+
+        let indent = line.len() - content.len();
+        while parents.last().is_some_and(|&(parent, _)| parent >= indent) {
+            parents.pop();
+        }
+        let key = unquote(key.trim());
+        let value = unquote(value.trim());
+
+        if value.is_empty() {
+            parents.push((indent, key));
+        } else if key == "command" {
+            let gesture: Vec<&str> = parents.iter().map(|&(_, key)| key).collect();
+            commands.push((gesture.join("."), value.to_string()));
+        }
+    }
+
+    commands
+}
+
+/// Removes the quotes around a scalar, or the comment after an unquoted one.
+fn unquote(scalar: &str) -> &str {
+    for quote in ['\'', '"'] {
+        if let Some(rest) = scalar.strip_prefix(quote) {
+            return rest.split_once(quote).map_or(rest, |(inner, _)| inner);
+        }
+    }
+    scalar.split(" #").next().unwrap_or_default().trim_end()
+}
+
+/// Translates an `xdotool key` command to the sequence its keys produce in a terminal.
+///
+/// # Returns
+/// `None` if the command is not `xdotool key`, or its sequence or error otherwise
+fn xdotool_keys(command: &str) -> Option<Result<Vec<u8>>> {
+    let words = split_words(command).ok()?;
+    let (program, words) = words.split_first()?;
+    let (subcommand, args) = words.split_first()?;
+    if program != "xdotool" || subcommand != "key" {
+        return None;
+    }
+
+    Some(
+        strip_options(args)
+            .iter()
+            .map(|combo| key_sequence(combo))
+            .collect::<Result<Vec<_>>>()
+            .map(|sequences| sequences.concat()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gesture_commands_are_found() {
+        let yaml = r#"
+swipe:
+  3:
+    left:
+      command: 'xdotool key alt+Right' # forward
+    right:
+      command: "xdotool key --clearmodifiers alt+Left"
+  4:
+    up:
+      command: xdotool set_desktop 1
+pinch:
+  in:
+    command: 'xdotool key ctrl+minus'
+---
+context:
+  application: Alacritty
+swipe:
+  3:
+    left:
+      command: 'xdotool key ctrl+Tab'
+"#;
+        assert_eq!(
+            gesture_commands(yaml),
+            [
+                ("swipe.3.left", "xdotool key alt+Right"),
+                ("swipe.3.right", "xdotool key --clearmodifiers alt+Left"),
+                ("swipe.4.up", "xdotool set_desktop 1"),
+                ("pinch.in", "xdotool key ctrl+minus"),
+                ("swipe.3.left", "xdotool key ctrl+Tab"),
+            ]
+            .map(|(gesture, command)| (gesture.to_string(), command.to_string()))
+        );
+    }
+
+    #[test]
+    fn gestures_map_their_keys_to_their_output() {
+        let commands = [
+            ("swipe.3.left", "xdotool key alt+Right"),
+            ("swipe.3.left", "xdotool key alt+b"),
+            ("swipe.4.up", "xdotool set_desktop 1"),
+            ("pinch.in", "xdotool key super+minus"),
+        ]
+        .map(|(gesture, command)| (gesture.to_string(), command.to_string()));
+
+        let gesture = parse_gesture("swipe.3.left:1b5b35337e").unwrap();
+        assert_eq!(
+            gesture_keymaps(&commands, &[gesture]).unwrap(),
+            [
+                (b"\x1b[1;3C".to_vec(), b"\x1b[53~".to_vec()),
+                (b"\x1bb".to_vec(), b"\x1b[53~".to_vec()),
+            ]
+        );
+        for unmappable in ["swipe.4.up:", "pinch.in:", "swipe.3.right:"] {
+            let gesture = parse_gesture(unmappable).unwrap();
+            assert!(gesture_keymaps(&commands, &[gesture]).is_err());
+        }
+        assert!(parse_gesture(":1b").is_err());
+    }
+
+    #[test]
+    fn only_xdotool_key_commands_are_translated() {
+        assert_eq!(
+            xdotool_keys("xdotool key --clearmodifiers alt+Left")
+                .unwrap()
+                .unwrap(),
+            b"\x1b[1;3D"
+        );
+        assert!(xdotool_keys("xdotool set_desktop 1").is_none());
+        assert!(xdotool_keys("xdotool key super+Left").unwrap().is_err());
+    }
+}
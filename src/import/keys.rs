@@ -0,0 +1,190 @@
+//! Terminal byte sequences of modified keys, shared by the importers of key names.
+
+use anyhow::{bail, Context, Result};
+
+/// How the keys and modifiers of a key combination are named.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyNames {
+    /// X keysyms like `Page_Up` and modifiers like `ctrl` or `Control_L`, as in xdotool.
+    Keysym,
+    /// Case-insensitive names like `pageup` and modifiers `ctrl`, `alt` and `shift`,
+    /// as in VS Code.
+    VsCode,
+}
+
+/// Keys with a fixed sequence, as sent by xterm in normal (non-application) mode,
+/// with their keysym and their VS Code name if VS Code has one.
+const NAMED_KEYS: &[(&str, Option<&str>, &[u8])] = &[
+    ("Return", Some("enter"), b"\r"),
+    ("KP_Enter", None, b"\r"),
+    ("Tab", Some("tab"), b"\t"),
+    ("BackSpace", Some("backspace"), b"\x7f"),
+    ("Escape", Some("escape"), b"\x1b"),
+    ("space", Some("space"), b" "),
+    ("Up", Some("up"), b"\x1b[A"),
+    ("Down", Some("down"), b"\x1b[B"),
+    ("Right", Some("right"), b"\x1b[C"),
+    ("Left", Some("left"), b"\x1b[D"),
+    ("Home", Some("home"), b"\x1b[H"),
+    ("End", Some("end"), b"\x1b[F"),
+    ("Insert", Some("insert"), b"\x1b[2~"),
+    ("Delete", Some("delete"), b"\x1b[3~"),
+    ("Prior", None, b"\x1b[5~"),
+    ("Page_Up", Some("pageup"), b"\x1b[5~"),
+    ("Next", None, b"\x1b[6~"),
+    ("Page_Down", Some("pagedown"), b"\x1b[6~"),
+    ("F1", Some("f1"), b"\x1bOP"),
+    ("F2", Some("f2"), b"\x1bOQ"),
+    ("F3", Some("f3"), b"\x1bOR"),
+    ("F4", Some("f4"), b"\x1bOS"),
+    ("F5", Some("f5"), b"\x1b[15~"),
+    ("F6", Some("f6"), b"\x1b[17~"),
+    ("F7", Some("f7"), b"\x1b[18~"),
+    ("F8", Some("f8"), b"\x1b[19~"),
+    ("F9", Some("f9"), b"\x1b[20~"),
+    ("F10", Some("f10"), b"\x1b[21~"),
+    ("F11", Some("f11"), b"\x1b[23~"),
+    ("F12", Some("f12"), b"\x1b[24~"),
+    ("minus", None, b"-"),
+    ("plus", None, b"+"),
+    ("equal", None, b"="),
+    ("underscore", None, b"_"),
+    ("period", None, b"."),
+    ("comma", None, b","),
+    ("slash", None, b"/"),
+    ("backslash", None, b"\\"),
+    ("semicolon", None, b";"),
+    ("colon", None, b":"),
+    ("apostrophe", None, b"'"),
+    ("quotedbl", None, b"\""),
+    ("bracketleft", None, b"["),
+    ("bracketright", None, b"]"),
+];
+
+/// Returns the terminal byte sequence of a single key combination like `ctrl+alt+a`.
+///
+/// # Arguments
+/// * `combo` - The whole combination, for error messages
+/// * `key` - Name of the key, or the character it types
+/// * `modifiers` - Names of the modifiers held with the key
+/// * `names` - How the key and modifiers are named
+///
+/// # Returns
+/// * `Ok(sequence)` on success
+/// * `Err(error)` if a name is unknown or the combination has no terminal sequence
+pub fn combo_sequence(
+    combo: &str,
+    key: &str,
+    modifiers: &[&str],
+    names: KeyNames,
+) -> Result<Vec<u8>> {
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    for &modifier in modifiers {
+        let flag = match (names, modifier) {
+            (KeyNames::Keysym, "ctrl" | "control" | "Control_L" | "Control_R") => &mut ctrl,
+            (KeyNames::Keysym, "alt" | "meta" | "Alt_L" | "Alt_R" | "Meta_L" | "Meta_R") => {
+                &mut alt
+            }
+            (KeyNames::Keysym, "shift" | "Shift_L" | "Shift_R") => &mut shift,
+            (KeyNames::VsCode, m) if m.eq_ignore_ascii_case("ctrl") => &mut ctrl,
+            (KeyNames::VsCode, m) if m.eq_ignore_ascii_case("alt") => &mut alt,
+            (KeyNames::VsCode, m) if m.eq_ignore_ascii_case("shift") => &mut shift,
+            _ => bail!("unsupported modifier '{modifier}' in '{combo}'"),
+        };
+        *flag = true;
+    }
+
+    let key = match names {
+        KeyNames::Keysym => key.to_string(),
+        KeyNames::VsCode => key.to_lowercase(),
+    };
+    let named = NAMED_KEYS.iter().find(|(keysym, vscode, _)| match names {
+        KeyNames::Keysym => *keysym == key,
+        KeyNames::VsCode => *vscode == Some(key.as_str()),
+    });
+    let sequence = match (named, shift) {
+        (Some(("Tab", ..)), true) => b"\x1b[Z".to_vec(),
+        (Some((.., sequence)), _) if sequence.len() > 2 => {
+            return Ok(with_modifier_parameter(sequence, ctrl, alt, shift));
+        }
+        (Some((.., sequence)), _) => sequence.to_vec(),
+        (None, _) if key.chars().count() != 1 => bail!("unsupported key '{key}' in '{combo}'"),
+        (None, true) => key.to_uppercase().into_bytes(),
+        (None, false) => key.into_bytes(),
+    };
+
+    with_modifiers(&sequence, ctrl, alt)
+        .with_context(|| format!("'{combo}' has no terminal sequence"))
+}
+
+/// Applies modifiers to the CSI or SS3 sequence of a named key, as xterm does.
+///
+/// The modifiers become a parameter, 1 plus 1 for Shift, 2 for Alt and 4 for Ctrl, so
+/// that Alt+Left is `ESC [ 1 ; 3 D` and Ctrl+Delete is `ESC [ 3 ; 5 ~`. Modified F1 to
+/// F4 are sent as CSI instead of SS3.
+///
+/// # Arguments
+/// * `sequence` - Sequence of the key without modifiers, like `ESC [ D` or `ESC O P`
+fn with_modifier_parameter(sequence: &[u8], ctrl: bool, alt: bool, shift: bool) -> Vec<u8> {
+    let modifier = 1 + u8::from(shift) + 2 * u8::from(alt) + 4 * u8::from(ctrl);
+    if modifier == 1 {
+        return sequence.to_vec();
+    }
+
+    let (parameter, last) = sequence[2..].split_at(sequence.len() - 3);
+    let parameter = if parameter.is_empty() {
+        &b"1"[..]
+    } else {
+        parameter
+    };
+    let mut modified = b"\x1b[".to_vec();
+    modified.extend_from_slice(parameter);
+    modified.extend_from_slice(format!(";{modifier}").as_bytes());
+    modified.extend_from_slice(last);
+    modified
+}
+
+/// Applies Ctrl and Alt to the sequence of an unmodified character key, as xterm does.
+///
+/// # Arguments
+/// * `sequence` - Sequence of the key without modifiers
+/// * `ctrl` - Whether Ctrl is held, which turns a character into a control code
+/// * `alt` - Whether Alt is held, which prefixes the sequence with ESC
+///
+/// # Returns
+/// The modified sequence, or `None` if Ctrl is held on a key without a control code
+pub fn with_modifiers(sequence: &[u8], ctrl: bool, alt: bool) -> Option<Vec<u8>> {
+    let mut sequence = if ctrl {
+        match sequence {
+            [b' ' | b'@' | b'2'] => vec![0x00],
+            [byte @ (b'a'..=b'z' | b'A'..=b'Z' | b'[' | b'\\' | b']' | b'^' | b'_')] => {
+                vec![byte & 0x1f]
+            }
+            _ => return None,
+        }
+    } else {
+        sequence.to_vec()
+    };
+    if alt {
+        sequence.insert(0, 0x1b);
+    }
+
+    Some(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modified_named_keys_get_a_parameter() {
+        let sequence =
+            |key, modifiers: &[&str]| combo_sequence(key, key, modifiers, KeyNames::Keysym);
+        assert_eq!(sequence("Left", &["alt"]).unwrap(), b"\x1b[1;3D");
+        assert_eq!(sequence("Up", &["ctrl", "shift"]).unwrap(), b"\x1b[1;6A");
+        assert_eq!(sequence("Delete", &["ctrl"]).unwrap(), b"\x1b[3;5~");
+        assert_eq!(sequence("F1", &["shift"]).unwrap(), b"\x1b[1;2P");
+        assert_eq!(sequence("F1", &[]).unwrap(), b"\x1bOP");
+        assert_eq!(sequence("Escape", &["alt"]).unwrap(), b"\x1b\x1b");
+    }
+}
@@ -0,0 +1,132 @@
+//! `--keymap-from-paste-history`: text expansion learned from a shell history file.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Triggers of the expansions, most common phrase first: Alt+1 through Alt+0.
+///
+/// Keymaps only match within a single read, and terminals send the ESC prefix of an
+/// Alt key together with the key, whereas two typed keys arrive in two reads.
+const TRIGGERS: [&[u8]; 10] = [
+    b"\x1b1", b"\x1b2", b"\x1b3", b"\x1b4", b"\x1b5", b"\x1b6", b"\x1b7", b"\x1b8", b"\x1b9",
+    b"\x1b0",
+];
+
+/// Generates keymaps expanding Alt+1 through Alt+0 to the most common multi-word entries of a history.
+///
+/// Both plain files with one entry per line and fish's `- cmd: ...` format are understood.
+///
+/// # Arguments
+/// * `path` - Path of the history file
+///
+/// # Returns
+/// * `Ok(keymaps)` on success, with fewer than ten entries for short histories
+/// * `Err(error)` if the file cannot be read
+pub fn keymaps_from_paste_history(path: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let history = std::fs::read(path)
+        .with_context(|| format!("cannot read history file '{}'", path.display()))?;
+    let history = String::from_utf8_lossy(&history);
+
+    let entries = history.lines().filter_map(|line| {
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            Some(unescape_fish(cmd))
+        } else if line.starts_with("  ") {
+            // Metadata of a fish entry, like `  when: 1700000000`
+            None
+        } else {
+            Some(line.to_string())
+        }
+    });
+
+    Ok(TRIGGERS
+        .iter()
+        .zip(most_common_phrases(entries, TRIGGERS.len()))
+        .map(|(trigger, phrase)| (trigger.to_vec(), phrase.into_bytes()))
+        .collect())
+}
+
+/// Returns up to `n` entries of at least two words, the most frequent first.
+///
+/// Ties are broken by the position of the first occurrence.
+fn most_common_phrases(entries: impl Iterator<Item = String>, n: usize) -> Vec<String> {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for (position, entry) in entries.enumerate() {
+        let entry = entry.trim();
+        if entry.split_whitespace().nth(1).is_none() {
+            continue;
+        }
+        counts.entry(entry.to_string()).or_insert((0, position)).0 += 1;
+    }
+
+    let mut phrases: Vec<(String, (usize, usize))> = counts.into_iter().collect();
+    phrases.sort_by_key(|&(_, (count, first))| (std::cmp::Reverse(count), first));
+    phrases
+        .into_iter()
+        .take(n)
+        .map(|(phrase, _)| phrase)
+        .collect()
+}
+
+/// Undoes the escaping of backslashes and newlines in fish history entries.
+fn unescape_fish(cmd: &str) -> String {
+    let mut result = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phrases_are_ranked_by_frequency() {
+        let entries = [
+            "ls",
+            "git status",
+            "cargo test",
+            "git status",
+            "cargo test",
+            "git status",
+        ];
+        assert_eq!(
+            most_common_phrases(entries.iter().map(|e| e.to_string()), 10),
+            ["git status", "cargo test"]
+        );
+    }
+
+    #[test]
+    fn triggers_arrive_in_one_read() {
+        use crate::processor::{KeyProcessor, Processor};
+
+        let keymap = TRIGGERS
+            .iter()
+            .map(|trigger| (trigger.to_vec(), b"git status".to_vec()))
+            .collect();
+        let mut processor = KeyProcessor::new(keymap);
+        // Typed keys and Alt keys, one per read as they arrive from a terminal
+        let typed: Vec<u8> = [&b"ls "[..], b";", b"1", b"\x1b2"]
+            .into_iter()
+            .flat_map(|read| processor.process(read))
+            .collect();
+        assert_eq!(typed, b"ls ;1git status");
+    }
+
+    #[test]
+    fn fish_escapes_are_undone() {
+        assert_eq!(unescape_fish(r"echo a\\nb\nc"), "echo a\\nb\nc");
+    }
+}
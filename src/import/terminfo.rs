@@ -0,0 +1,107 @@
+//! `--keymap-from-xterm-terminfo`: translating a terminal's key sequences to xterm's.
+
+use anyhow::{Context, Result};
+use terminfo::{Database, Value};
+
+/// Key capabilities and the sequence xterm sends for them in normal (non-application) mode.
+const CANONICAL_KEYS: &[(&str, &[u8])] = &[
+    ("kcuu1", b"\x1b[A"),
+    ("kcud1", b"\x1b[B"),
+    ("kcuf1", b"\x1b[C"),
+    ("kcub1", b"\x1b[D"),
+    ("khome", b"\x1b[H"),
+    ("kend", b"\x1b[F"),
+    ("kich1", b"\x1b[2~"),
+    ("kdch1", b"\x1b[3~"),
+    ("kpp", b"\x1b[5~"),
+    ("knp", b"\x1b[6~"),
+    ("kcbt", b"\x1b[Z"),
+    ("kbs", b"\x7f"),
+    ("kf1", b"\x1bOP"),
+    ("kf2", b"\x1bOQ"),
+    ("kf3", b"\x1bOR"),
+    ("kf4", b"\x1bOS"),
+    ("kf5", b"\x1b[15~"),
+    ("kf6", b"\x1b[17~"),
+    ("kf7", b"\x1b[18~"),
+    ("kf8", b"\x1b[19~"),
+    ("kf9", b"\x1b[20~"),
+    ("kf10", b"\x1b[21~"),
+    ("kf11", b"\x1b[23~"),
+    ("kf12", b"\x1b[24~"),
+];
+
+/// Generates keymaps translating the key sequences of `term_name` to the canonical xterm ones.
+///
+/// Keys the entry does not define, or defines identically to xterm, yield no keymap.
+///
+/// # Arguments
+/// * `term_name` - Name of the terminfo entry, e.g. `rxvt-unicode`
+///
+/// # Returns
+/// * `Ok(keymaps)` on success
+/// * `Err(error)` if the entry cannot be found or parsed
+pub fn keymaps_from_terminfo(term_name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let database = Database::from_name(term_name)
+        .with_context(|| format!("failed to read terminfo entry '{term_name}'"))?;
+
+    Ok(translations(|capability| match database.raw(capability) {
+        Some(Value::String(sequence)) => Some(sequence.clone()),
+        _ => None,
+    }))
+}
+
+/// Pairs the sequence of each key of an entry with the canonical one, where they differ.
+///
+/// Terminfo describes keys in keypad transmit mode, which applications enable with
+/// `smkx`. Many entries, xterm's own included, therefore give cursor and editing keys
+/// as `ESC O x`, which terminals only send in that mode and which the application
+/// then expects as is: such keys are skipped rather than rewritten.
+///
+/// # Arguments
+/// * `sequence` - Sequence of a key capability in the entry, if defined
+fn translations(sequence: impl Fn(&str) -> Option<Vec<u8>>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    CANONICAL_KEYS
+        .iter()
+        .filter_map(|&(capability, canonical)| {
+            let sequence = sequence(capability)?;
+            let application_mode =
+                sequence.starts_with(b"\x1bO") && canonical.starts_with(b"\x1b[");
+            (!sequence.is_empty() && sequence != canonical && !application_mode)
+                .then(|| (sequence, canonical.to_vec()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differing_keys_are_translated_except_in_application_mode() {
+        // Keys of rxvt, with the application mode cursor keys of xterm
+        let entry = [
+            ("kcuu1", &b"\x1bOA"[..]),
+            ("kcud1", b"\x1b[B"),
+            ("khome", b"\x1b[7~"),
+            ("kbs", b"\x08"),
+            ("kf1", b"\x1b[11~"),
+            ("kf2", b"\x1bOQ"),
+            ("kf5", b""),
+        ];
+        let keymaps = translations(|capability| {
+            entry
+                .iter()
+                .find(|(name, _)| *name == capability)
+                .map(|(_, sequence)| sequence.to_vec())
+        });
+        assert_eq!(
+            keymaps,
+            [
+                (b"\x1b[7~".to_vec(), b"\x1b[H".to_vec()),
+                (b"\x08".to_vec(), b"\x7f".to_vec()),
+                (b"\x1b[11~".to_vec(), b"\x1bOP".to_vec()),
+            ]
+        );
+    }
+}
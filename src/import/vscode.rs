@@ -0,0 +1,167 @@
+//! `--keymap-from-vscode`: `type` key bindings from a VS Code `keybindings.json`.
+//!
+//! Only bindings running the `type` command are imported, as the only ones that
+//! stand for text sent to a terminal:
+//!
+//! ```json
+//! {"key": "ctrl+shift+e", "command": "type", "args": {"text": "\u001b"}}
+//! ```
+
+use super::keys::{combo_sequence, KeyNames};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// One entry of `keybindings.json`; `when` and other fields are ignored.
+#[derive(Deserialize)]
+struct Keybinding {
+    key: String,
+    command: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+/// Generates keymaps from the `type` bindings of a VS Code `keybindings.json`.
+///
+/// Comments and trailing commas are accepted, as in VS Code. Bindings with keys that
+/// have no terminal sequence, like `cmd+k` or `ctrl+1`, and chords are skipped with
+/// a warning.
+///
+/// # Arguments
+/// * `path` - Path of the file
+///
+/// # Returns
+/// * `Ok(keymaps)` on success
+/// * `Err(error)` if the file cannot be read or is not a list of key bindings
+pub fn keymaps_from_vscode(path: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read key bindings '{}'", path.display()))?;
+    let bindings: Vec<Keybinding> = serde_json::from_str(&strip_jsonc(&json))
+        .with_context(|| format!("invalid key bindings '{}'", path.display()))?;
+
+    Ok(bindings
+        .into_iter()
+        .filter(|binding| binding.command == "type")
+        .filter_map(|binding| {
+            let Some(text) = binding.args.get("text").and_then(|text| text.as_str()) else {
+                eprintln!(
+                    "cli-keyhook: warning: skipping binding of '{}' without text",
+                    binding.key
+                );
+                return None;
+            };
+            match key_sequence(&binding.key) {
+                Ok(sequence) => Some((sequence, text.as_bytes().to_vec())),
+                Err(e) => {
+                    eprintln!(
+                        "cli-keyhook: warning: skipping binding of '{}': {e}",
+                        binding.key
+                    );
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// Removes comments and trailing commas, turning VS Code's JSON with comments into JSON.
+fn strip_jsonc(jsonc: &str) -> String {
+    let mut json = String::with_capacity(jsonc.len());
+    let mut chars = jsonc.chars().peekable();
+    // Position in `json` of a comma that is dropped if a closing bracket follows it
+    let mut comma = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                comma = None;
+                json.push(c);
+                while let Some(c) = chars.next() {
+                    json.push(c);
+                    match c {
+                        '\\' => json.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+            }
+            ']' | '}' => {
+                if let Some(position) = comma.take() {
+                    json.remove(position);
+                }
+                json.push(c);
+            }
+            ',' => {
+                comma = Some(json.len());
+                json.push(c);
+            }
+            c if c.is_whitespace() => json.push(c),
+            _ => {
+                comma = None;
+                json.push(c);
+            }
+        }
+    }
+
+    json
+}
+
+/// Returns the terminal byte sequence for a key like `ctrl+a`.
+///
+/// Chords like `ctrl+k ctrl+c` are rejected: their keys arrive in separate reads, and
+/// keymaps only match within one.
+fn key_sequence(key: &str) -> Result<Vec<u8>> {
+    let combo = match key.split_whitespace().collect::<Vec<_>>()[..] {
+        [combo] => combo,
+        [] => bail!("empty key"),
+        _ => bail!("chords are not supported, as their keys are typed in separate reads"),
+    };
+    let (key, modifiers) = match combo.rsplit_once('+') {
+        // `ctrl++` ends with the plus key
+        Some((modifiers, "")) => (&combo[combo.len() - 1..], modifiers.trim_end_matches('+')),
+        Some((modifiers, key)) => (key, modifiers),
+        None => (combo, ""),
+    };
+
+    let modifiers: Vec<&str> = modifiers.split('+').filter(|m| !m.is_empty()).collect();
+    combo_sequence(combo, key, &modifiers, KeyNames::VsCode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_translated_but_not_chords() {
+        assert_eq!(key_sequence("ctrl+a").unwrap(), b"\x01");
+        assert_eq!(key_sequence("Ctrl+Alt+X").unwrap(), b"\x1b\x18");
+        assert_eq!(key_sequence("shift+tab").unwrap(), b"\x1b[Z");
+        assert!(key_sequence("ctrl+k ctrl+c").is_err());
+        assert_eq!(key_sequence("alt++").unwrap(), b"\x1b+");
+        assert!(key_sequence("cmd+k").is_err());
+        assert_eq!(key_sequence("ctrl+up").unwrap(), b"\x1b[1;5A");
+        assert!(key_sequence("ctrl+1").is_err());
+    }
+
+    #[test]
+    fn comments_and_trailing_commas_are_stripped() {
+        let jsonc = r#"// Place your key bindings in this file
+[
+    /* "a // b" */ {"key": "ctrl+e", "command": "type", "args": {"text": "// not a comment,]"},},
+]"#;
+        let bindings: Vec<Keybinding> = serde_json::from_str(&strip_jsonc(jsonc)).unwrap();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].args["text"], "// not a comment,]");
+    }
+}
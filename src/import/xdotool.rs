@@ -0,0 +1,172 @@
+//! `--keymap-from-xdotool-script`: macros written as xdotool scripts.
+//!
+//! Each paragraph of the script is one macro: its first command is `key` with the
+//! keystroke triggering it, and the following `key` and `type` commands produce what
+//! is sent instead.
+//!
+//! ```text
+//! key ctrl+g
+//! type --delay 20 "git status"
+//! key --clearmodifiers Return
+//! ```
+
+use super::keys::{combo_sequence, KeyNames};
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Options of `key` and `type` that are followed by a value.
+const OPTIONS_WITH_VALUE: &[&str] = &["--delay", "--window", "--repeat", "--repeat-delay"];
+
+/// Generates keymaps from the macros of an xdotool script.
+///
+/// Options of `key` and `type`, like `--clearmodifiers` and `--delay`, are ignored,
+/// and so are `sleep` commands and comments.
+///
+/// # Arguments
+/// * `path` - Path of the script
+///
+/// # Returns
+/// * `Ok(keymaps)` on success
+/// * `Err(error)` if the file cannot be read or uses unsupported commands or keys
+pub fn keymaps_from_xdotool_script(path: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let script = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read xdotool script '{}'", path.display()))?;
+    parse_script(&script).with_context(|| format!("invalid xdotool script '{}'", path.display()))
+}
+
+/// Parses the macros of a script, see [`keymaps_from_xdotool_script`].
+fn parse_script(script: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut keymaps = Vec::new();
+    // Trigger and output of the current paragraph
+    let mut current: Option<(Vec<u8>, Vec<u8>)> = None;
+
+    for (number, line) in (1..).zip(script.lines()) {
+        let words = split_words(line).with_context(|| format!("line {number}"))?;
+        let Some((command, args)) = words.split_first() else {
+            keymaps.extend(current.take());
+            continue;
+        };
+        if command.starts_with('#') || command == "sleep" {
+            continue;
+        }
+        let args = strip_options(args);
+
+        let bytes = match command.as_str() {
+            "key" => args
+                .iter()
+                .map(|combo| key_sequence(combo))
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("line {number}"))?
+                .concat(),
+            "type" => args.join(" ").into_bytes(),
+            _ => bail!("line {number}: unsupported xdotool command '{command}'"),
+        };
+
+        match &mut current {
+            Some((_, output)) => output.extend(bytes),
+            None if command == "key" => current = Some((bytes, Vec::new())),
+            None => bail!("line {number}: a macro must start with the 'key' triggering it"),
+        }
+    }
+    keymaps.extend(current);
+
+    Ok(keymaps)
+}
+
+/// Splits a line into words like a shell, honoring quotes and backslashes.
+pub(super) fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => {
+                let escaped = chars.next().context("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("unterminated quote");
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+/// Drops the leading options of a `key` or `type` command, with their values.
+pub(super) fn strip_options(args: &[String]) -> &[String] {
+    let mut args = args;
+    while let Some((option, rest)) = args.split_first() {
+        if !option.starts_with("--") {
+            break;
+        }
+        args = if OPTIONS_WITH_VALUE.contains(&option.as_str()) {
+            rest.get(1..).unwrap_or_default()
+        } else {
+            rest
+        };
+    }
+    args
+}
+
+/// Returns the terminal byte sequence for a key combination like `ctrl+alt+a`.
+pub(super) fn key_sequence(combo: &str) -> Result<Vec<u8>> {
+    let mut parts: Vec<&str> = combo.split('+').collect();
+    // `ctrl++` and a bare `+` both end with the plus key
+    if combo.ends_with("++") || combo == "+" {
+        parts.retain(|part| !part.is_empty());
+        parts.push("plus");
+    }
+    let (key, modifiers) = parts.split_last().context("empty key combination")?;
+
+    combo_sequence(combo, key, modifiers, KeyNames::Keysym)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_combinations_are_translated() {
+        assert_eq!(key_sequence("ctrl+a").unwrap(), b"\x01");
+        assert_eq!(key_sequence("alt+shift+x").unwrap(), b"\x1bX");
+        assert_eq!(key_sequence("shift+Tab").unwrap(), b"\x1b[Z");
+        assert_eq!(key_sequence("ctrl+bracketleft").unwrap(), b"\x1b");
+        assert_eq!(key_sequence("F5").unwrap(), b"\x1b[15~");
+        assert!(key_sequence("super+a").is_err());
+        assert_eq!(key_sequence("ctrl+Up").unwrap(), b"\x1b[1;5A");
+        assert!(key_sequence("ctrl+1").is_err());
+    }
+
+    #[test]
+    fn paragraphs_are_macros() {
+        let script = "\
+# Status of the repository
+key ctrl+g
+type --delay 20 \"git status\"
+sleep 0.1
+key --clearmodifiers Return
+
+key F5
+key ctrl+c Up Return
+";
+        assert_eq!(
+            parse_script(script).unwrap(),
+            [
+                (b"\x07".to_vec(), b"git status\r".to_vec()),
+                (b"\x1b[15~".to_vec(), b"\x03\x1b[A\r".to_vec()),
+            ]
+        );
+        assert!(parse_script("type oops").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! `--lock-key` and `--input-mode-indicator`: toggling remapping while the command runs.
+//!
+//! The lock key is swallowed wherever it is seen, on stdin and the control socket
+//! alike, and every occurrence turns remapping off or back on for both.
+
+use crate::processor::Processor;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Parses a mode indicator in the format "on_hex:off_hex".
+///
+/// Either side may be empty, so that only one of the transitions is shown.
+///
+/// # Arguments
+/// * `s` - Hex encoded bytes written when remapping is enabled and disabled
+///
+/// # Returns
+/// * `Ok((on, off))` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_indicator(s: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let (on, off) = s.split_once(':').ok_or_else(|| {
+        format!("invalid mode indicator format '{s}', expected format 'on_hex:off_hex'")
+    })?;
+    let decode = |hex: &str| match hex {
+        "" => Ok(Vec::new()),
+        _ => crate::hex_decode(hex).map_err(|e| format!("invalid hex string '{hex}' ({e})")),
+    };
+
+    Ok((decode(on)?, decode(off)?))
+}
+
+/// Whether remapping is enabled, shared by every path input arrives on.
+#[derive(Clone)]
+pub struct RemapLock {
+    enabled: Arc<AtomicBool>,
+    /// Bytes written to stdout when remapping is enabled and disabled, if any
+    indicator: Option<Arc<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl RemapLock {
+    /// Creates a lock with remapping enabled.
+    ///
+    /// # Arguments
+    /// * `indicator` - Bytes written to the user's terminal on every toggle, if any
+    pub fn new(indicator: Option<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(true)),
+            indicator: indicator.map(Arc::new),
+        }
+    }
+
+    /// Turns remapping off if it was on and on if it was off, then shows the new mode.
+    fn toggle(&self) {
+        let enabled = !self.enabled.fetch_xor(true, Ordering::AcqRel);
+        if let Some(indicator) = &self.indicator {
+            let (on, off) = &**indicator;
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(if enabled { on } else { off });
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// Swallows the lock key and bypasses the wrapped processor while remapping is disabled.
+pub struct LockProcessor {
+    inner: Box<dyn Processor>,
+    key: Vec<u8>,
+    lock: RemapLock,
+}
+
+impl LockProcessor {
+    pub fn new(inner: Box<dyn Processor>, key: Vec<u8>, lock: RemapLock) -> Self {
+        Self { inner, key, lock }
+    }
+
+    /// Passes a part of the input without lock keys on, remapped only if enabled.
+    fn forward(&mut self, part: &[u8]) -> Vec<u8> {
+        if self.lock.enabled.load(Ordering::Acquire) {
+            self.inner.process(part)
+        } else {
+            part.to_vec()
+        }
+    }
+}
+
+impl Processor for LockProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+
+        while i < input.len() {
+            if !input[i..].starts_with(&self.key) {
+                i += 1;
+                continue;
+            }
+
+            let part = self.forward(&input[start..i]);
+            output.extend(part);
+            self.lock.toggle();
+            i += self.key.len();
+            start = i;
+        }
+        let rest = self.forward(&input[start..]);
+        output.extend(rest);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::KeyProcessor;
+
+    #[test]
+    fn lock_key_toggles_remapping() {
+        let keymap = [(b"a".to_vec(), b"b".to_vec())].into_iter().collect();
+        let lock = RemapLock::new(None);
+        let mut stdin = LockProcessor::new(
+            Box::new(KeyProcessor::new(keymap)),
+            b"\x1c".to_vec(),
+            lock.clone(),
+        );
+
+        assert_eq!(stdin.process(b"a\x1ca\x1ca"), b"bab");
+        assert_eq!(stdin.process(b"\x1c"), b"");
+        assert!(!lock.enabled.load(Ordering::Acquire));
+        assert_eq!(stdin.process(b"a"), b"a");
+    }
+
+    #[test]
+    fn indicator_sides_may_be_empty() {
+        assert_eq!(
+            parse_indicator("1b5d31323b7265641b5c:"),
+            Ok((b"\x1b]12;red\x1b\\".to_vec(), Vec::new()))
+        );
+        assert!(parse_indicator("1b").is_err());
+    }
+}
@@ -1,46 +1,1003 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use nix::pty::Winsize;
-use nix::sys::select::FdSet;
-use nix::sys::termios::{self, InputFlags, LocalFlags, OutputFlags, Termios};
-use nix::sys::time::TimeVal;
+use nix::sys::termios::{self, BaudRate, InputFlags, LocalFlags, OutputFlags, Termios};
 use nix::sys::wait::WaitStatus;
 use nix::unistd::{ForkResult, Pid};
 use signal_hook::{consts::SIGWINCH, iterator::Signals};
 use std::collections::HashMap;
 use std::ffi::CString;
 use std::io;
-use std::os::fd::{AsFd, OwnedFd};
-use std::os::unix::io::AsRawFd;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+use codec::Codec;
+use poller::{Backend, Event, Source};
+use processor::{
+    ByteSwapProcessor, Chain, CountedRemap, CountingProcessor, KeyProcessor, Occurrence, Processor,
+};
+use rate::{InputRate, RateEstimator};
+
+#[cfg(target_os = "linux")]
+mod affinity;
+mod alias;
+#[cfg(target_os = "linux")]
+mod attach;
+mod charmap;
+mod clipboard;
+mod codec;
+mod control;
+#[cfg(target_os = "linux")]
+mod cpu_limit;
+#[cfg(debug_ptrace)]
+mod debug_ptrace;
+mod fd_passing;
+mod hex_dump;
+mod hook;
+mod import;
+mod lock;
+#[cfg(feature = "memory-profile")]
+mod memory_profile;
+mod midi;
+mod poller;
+mod processor;
+mod profiling;
+mod pty_slave;
+mod qr;
+mod rate;
+#[cfg(target_os = "linux")]
+mod rss_limit;
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+mod seccomp;
+mod serial;
+mod stream_split;
+#[cfg(test)]
+mod test_harness;
+
+/// Size of the buffer for reads from the PTY master unless `--pty-buffer-size` is given.
+const DEFAULT_PTY_BUFFER_SIZE: usize = 16384;
+
+/// Size of the buffer for reads from stdin unless `--input-buffer-size` is given.
+const DEFAULT_INPUT_BUFFER_SIZE: usize = 16384;
 
 /// A mapping from input byte sequences to output byte sequences for key remapping.
 type KeyMap = HashMap<Vec<u8>, Vec<u8>>;
 
+/// Settings of the parent process, shared by `run_pty_wrapper` and `run_attached`.
+struct ParentConfig {
+    /// Key mapping configuration for input transformation
+    keymap: KeyMap,
+    /// Key toggling remapping and the state it toggles, if any
+    lock_key: Option<(Vec<u8>, lock::RemapLock)>,
+    /// Event loop backend waiting for input
+    backend: Backend,
+    /// Longest wait for input before checking whether the child exited
+    wait_timeout: Duration,
+    /// Size of the buffer for reads from stdin
+    input_buffer_size: usize,
+    /// Size of the buffer for reads from the PTY master
+    pty_buffer_size: usize,
+    /// Path of the control socket to listen on, if any
+    control_socket: Option<PathBuf>,
+    /// Path of a socket to receive an additional input fd from, if any
+    fd_passing: Option<PathBuf>,
+    /// V4L2 camera device whose QR codes are typed as input, if any
+    input_from_qr: Option<PathBuf>,
+    /// ALSA sequencer port whose notes are typed as input, if any
+    input_from_midi: Option<String>,
+    /// Keystrokes typed for the notes of `input_from_midi`
+    midi_keymap: HashMap<u8, Vec<u8>>,
+    /// Prefix of the QR code images the output is written to, if any
+    output_to_qr: Option<PathBuf>,
+    /// Delimiter of the output segments encoded in QR codes
+    output_split: Vec<u8>,
+    /// Patterns of output lines also written to the stdin of their command
+    output_stream_splits: Vec<(regex::bytes::Regex, String)>,
+    /// Serial device read as additional input, if any
+    input_from_serial: Option<PathBuf>,
+    /// Serial device the output is also written to, if any
+    output_to_serial: Option<PathBuf>,
+    /// Baud rate of the serial devices
+    serial_baud: BaudRate,
+    /// Clipboard content pasted into the PTY once the session has started, if any
+    clipboard_input: Option<Vec<u8>>,
+    /// Most output bytes copied to the clipboard at the end of the session, if output is copied
+    output_to_clipboard: Option<usize>,
+    /// Size of the byte groups reversed in user input before decoding, if any
+    byte_swap: Option<usize>,
+    /// Interpretation of bytes read from stdin
+    input_codec: Codec,
+    /// Characters replaced in user input before keymap matching
+    input_char_map: HashMap<char, char>,
+    /// Interpretation of bytes read from the PTY master
+    output_codec: Codec,
+    /// Characters replaced in the output of the command
+    output_char_map: HashMap<char, char>,
+    /// Key mapping configuration for output transformation
+    output_keymap: KeyMap,
+    /// Whether the output is replaced by a hex dump of the raw bytes read from the PTY
+    output_hex_dump_only: bool,
+    /// Resident memory limit enforced on a forked child, if any
+    #[cfg(target_os = "linux")]
+    rss_limit: Option<rss_limit::RssLimit>,
+    /// Share of one core a forked child may average under `RLIMIT_CPU`, if limited this way
+    #[cfg(target_os = "linux")]
+    cpu_budget: Option<u32>,
+    /// Whether the writes of a forked child to stdout and stderr are traced and logged
+    #[cfg(debug_ptrace)]
+    debug_ptrace: bool,
+    /// How the input rate is estimated, if it is tracked
+    input_rate_estimator: Option<RateEstimator>,
+    /// Shell command run for every chunk of input, if any
+    input_event_hook: Option<String>,
+    /// Shell commands run when their key is matched
+    on_match_exec: Vec<(Vec<u8>, String)>,
+    /// Remaps applied depending on how often their key has occurred
+    counted_remaps: Vec<CountedRemap>,
+}
+
+impl Default for ParentConfig {
+    fn default() -> Self {
+        Self {
+            keymap: KeyMap::new(),
+            lock_key: None,
+            backend: Backend::default(),
+            wait_timeout: Duration::from_millis(100),
+            input_buffer_size: DEFAULT_INPUT_BUFFER_SIZE,
+            pty_buffer_size: DEFAULT_PTY_BUFFER_SIZE,
+            control_socket: None,
+            fd_passing: None,
+            input_from_qr: None,
+            input_from_midi: None,
+            midi_keymap: HashMap::new(),
+            output_to_qr: None,
+            output_split: b"\r\n".to_vec(),
+            output_stream_splits: Vec::new(),
+            input_from_serial: None,
+            output_to_serial: None,
+            serial_baud: BaudRate::B9600,
+            clipboard_input: None,
+            output_to_clipboard: None,
+            byte_swap: None,
+            input_codec: Codec::Utf8,
+            input_char_map: HashMap::new(),
+            output_codec: Codec::Binary,
+            output_char_map: HashMap::new(),
+            output_keymap: KeyMap::new(),
+            output_hex_dump_only: false,
+            #[cfg(target_os = "linux")]
+            rss_limit: None,
+            #[cfg(target_os = "linux")]
+            cpu_budget: None,
+            #[cfg(debug_ptrace)]
+            debug_ptrace: false,
+            input_rate_estimator: None,
+            input_event_hook: None,
+            on_match_exec: Vec::new(),
+            counted_remaps: Vec::new(),
+        }
+    }
+}
+
+impl ParentConfig {
+    /// Builds the processor chains for input and output.
+    ///
+    /// Input observers see the raw bytes, then codecs come so that all later stages
+    /// see decoded bytes.
+    fn chains(&self) -> (Chain, Chain) {
+        let mut input = Chain::default();
+        let rate = self.input_rate_estimator.map(|estimator| {
+            let rate = InputRate::default();
+            input.push(Box::new(rate::RateProcessor::new(estimator, rate.clone())));
+            rate
+        });
+        if let Some(command) = &self.input_event_hook {
+            input.push(Box::new(hook::InputEventHook::new(command.clone(), rate)));
+        }
+        if let Some(n) = self.byte_swap {
+            input.push(Box::new(ByteSwapProcessor::new(n)));
+        }
+        if let Some(codec) = self.input_codec.processor() {
+            input.push(codec);
+        }
+        if !self.input_char_map.is_empty() {
+            input.push(Box::new(charmap::CharMapProcessor::new(
+                self.input_char_map.clone(),
+                false,
+            )));
+        }
+        let mut key_processor = KeyProcessor::new(self.keymap.clone());
+        for (key, command) in &self.on_match_exec {
+            key_processor = key_processor.on_match_exec(key.clone(), command.clone());
+        }
+        let mut key_processor: Box<dyn Processor> = Box::new(key_processor);
+        for remap in &self.counted_remaps {
+            key_processor = Box::new(CountingProcessor::new(key_processor, remap.clone()));
+        }
+        input.push(self.with_lock(key_processor));
+
+        let mut output = Chain::default();
+        // The dump itself is added by `parent_process`, after the copies of the output
+        if self.output_hex_dump_only {
+            return (input, output);
+        }
+        if let Some(codec) = self.output_codec.processor() {
+            output.push(codec);
+        }
+        if !self.output_char_map.is_empty() {
+            output.push(Box::new(charmap::CharMapProcessor::new(
+                self.output_char_map.clone(),
+                true,
+            )));
+        }
+        if !self.output_keymap.is_empty() {
+            output.push(Box::new(KeyProcessor::new(self.output_keymap.clone())));
+        }
+
+        (input, output)
+    }
+
+    /// Wraps the remapping of an input path so that `--lock-key` can toggle it.
+    fn with_lock(&self, remapping: Box<dyn Processor>) -> Box<dyn Processor> {
+        match &self.lock_key {
+            Some((key, lock)) => Box::new(lock::LockProcessor::new(
+                remapping,
+                key.clone(),
+                lock.clone(),
+            )),
+            None => remapping,
+        }
+    }
+}
+
+/// Settings of the child and its PTY, applied before executing the command.
+#[derive(Default)]
+struct ChildConfig {
+    /// First element of the argument vector instead of the command, if any
+    argv0: Option<String>,
+    /// Mode set on the PTY slave device before forking, if any
+    slave_mode: Option<u32>,
+    /// Owner set on the PTY slave device before forking, if any
+    slave_owner: Option<(nix::unistd::Uid, nix::unistd::Gid)>,
+    /// Echo setting forced on the PTY slave, if any
+    echo: Option<bool>,
+    /// Cores the command is pinned to, if any
+    #[cfg(target_os = "linux")]
+    cpu_set: Option<nix::sched::CpuSet>,
+    /// Cgroup v2 the command joins, limiting its CPU usage, if any
+    #[cfg(target_os = "linux")]
+    cpu_cgroup: Option<PathBuf>,
+    /// Seccomp filter loaded right before `execvp`, if any
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    seccomp_filter: Option<libseccomp::ScmpFilterContext>,
+}
+
+/// What the child needs to execute the command, prepared before forking so that the
+/// child does not have to allocate.
+struct Exec {
+    /// Command looked up in `PATH`
+    command: CString,
+    /// Argument vector, starting with argv[0]
+    args: Vec<CString>,
+    /// `cgroup.procs` of the cgroup the command joins, if any
+    #[cfg(target_os = "linux")]
+    cgroup_procs: Option<std::fs::File>,
+}
+
+impl Exec {
+    /// Prepares the execution of `command` with `args` under `child_config`.
+    fn new(command: &str, args: &[String], child_config: &ChildConfig) -> Result<Self> {
+        let command = CString::new(command)?;
+        // The binary is still looked up by `command`, whatever argv[0] says
+        let argv0 = match &child_config.argv0 {
+            Some(argv0) => CString::new(argv0.as_str())?,
+            None => command.clone(),
+        };
+        let mut exec_args: Vec<CString> = vec![argv0];
+        for arg in args {
+            exec_args.push(CString::new(arg.as_str())?);
+        }
+
+        Ok(Self {
+            command,
+            args: exec_args,
+            #[cfg(target_os = "linux")]
+            cgroup_procs: child_config
+                .cpu_cgroup
+                .as_deref()
+                .map(cpu_limit::open_cgroup)
+                .transpose()?,
+        })
+    }
+}
+
 /// Command line arguments for the CLI key hook program.
 #[derive(Parser)]
 #[command(name = "cli-keyhook")]
 #[command(version)]
 #[command(about = "A CLI wrapper that intercepts and remaps keyboard input")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(disable_help_subcommand = true)]
 struct Args {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+
     /// Map input bytes to output bytes (hex format)
     #[arg(short = 'k', long = "keymap", value_name = "INPUT:OUTPUT", value_parser = parse_keymap)]
     keymaps: Vec<(Vec<u8>, Vec<u8>)>,
 
+    /// Read NAME=HEX aliases from a file, to use as $NAME in --keymap values
+    #[arg(long = "keymap-alias-file", value_name = "PATH")]
+    keymap_alias_file: Option<PathBuf>,
+
+    /// Toggle remapping off and back on whenever this key is pressed (hex format)
+    #[arg(long = "lock-key", value_name = "HEX", value_parser = hex_decode)]
+    lock_key: Option<::std::vec::Vec<u8>>,
+
+    /// Write ON to the terminal when --lock-key enables remapping and OFF when it disables it (hex format)
+    #[arg(
+        long = "input-mode-indicator",
+        value_name = "ON:OFF",
+        value_parser = lock::parse_indicator,
+        requires = "lock_key"
+    )]
+    input_mode_indicator: Option<(Vec<u8>, Vec<u8>)>,
+
+    /// Direction of the --keymap entries following this flag [default: input-only]
+    #[arg(long = "keymap-scope", value_name = "SCOPE")]
+    keymap_scopes: Vec<KeymapScope>,
+
+    /// Translate the key sequences of a terminfo entry to the ones of xterm
+    #[arg(long = "keymap-from-xterm-terminfo", value_name = "TERM_NAME")]
+    keymap_from_xterm_terminfo: Vec<String>,
+
+    /// Reverse every group of N bytes of input before keymaps are applied, for byte-swapped keyboards
+    #[arg(
+        long = "keymap-byte-swap",
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..)
+    )]
+    keymap_byte_swap: Option<usize>,
+
+    /// Expand Alt+1 through Alt+0 to the ten most common multi-word entries of a shell history file
+    #[arg(long = "keymap-from-paste-history", value_name = "PATH")]
+    keymap_from_paste_history: Vec<PathBuf>,
+
+    /// Import macros from an xdotool script: each paragraph maps its first `key` to the rest
+    #[arg(long = "keymap-from-xdotool-script", value_name = "PATH")]
+    keymap_from_xdotool_script: Vec<PathBuf>,
+
+    /// Import the `type` bindings of a VS Code keybindings.json
+    #[arg(
+        long = "keymap-from-vscode",
+        visible_alias = "keymap-from-keybindings-json",
+        value_name = "PATH"
+    )]
+    keymap_from_vscode: Vec<PathBuf>,
+
+    /// Import the keystrokes that fusuma's touchpad gestures send with `xdotool key`, to remap with --fusuma-gesture
+    #[arg(
+        long = "keymap-from-fusuma",
+        value_name = "PATH",
+        requires = "fusuma_gestures"
+    )]
+    keymap_from_fusuma: Vec<PathBuf>,
+
+    /// Type OUTPUT (hex format) instead of the keys a gesture of --keymap-from-fusuma sends, like swipe.3.left
+    #[arg(
+        long = "fusuma-gesture",
+        value_name = "GESTURE:OUTPUT",
+        value_parser = import::parse_gesture,
+        requires = "keymap_from_fusuma"
+    )]
+    fusuma_gestures: Vec<(String, Vec<u8>)>,
+
+    /// Read ';'-separated INPUT:OUTPUT keymaps from an environment variable
+    #[arg(long = "keymap-from-env", value_name = "VAR")]
+    keymap_from_env: Vec<String>,
+
+    /// Periodically dump jemalloc heap profiles to `<PATH>.<seq>.jemalloc.prof`
+    #[cfg(feature = "memory-profile")]
+    #[arg(long = "memory-profile", value_name = "PATH")]
+    memory_profile: Option<std::path::PathBuf>,
+
+    /// Interval between two heap profile dumps
+    #[cfg(feature = "memory-profile")]
+    #[arg(
+        long = "memory-profile-interval",
+        value_name = "SECONDS",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    memory_profile_interval: u64,
+
+    /// Attach to the PTY of an already-running process instead of spawning a command
+    #[cfg(target_os = "linux")]
+    #[arg(long = "attach", value_name = "PID", conflicts_with_all = ["command", "args"])]
+    attach: Option<i32>,
+
+    /// Pin the command to the performance or efficiency cores of heterogeneous CPUs
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "child-affinity-strategy",
+        value_name = "STRATEGY",
+        default_value = "any",
+        conflicts_with = "attach"
+    )]
+    child_affinity_strategy: affinity::AffinityStrategy,
+
+    /// Terminate the command once its resident memory exceeds MB megabytes (Linux only)
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "child-rss-limit",
+        value_name = "MB",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "attach"
+    )]
+    child_rss_limit: Option<u64>,
+
+    /// Time between two checks of --child-rss-limit
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "rss-check-interval",
+        value_name = "MS",
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    rss_check_interval: u64,
+
+    /// Limit the command to PERCENT of one core with a soft RLIMIT_CPU, killing it with SIGXCPU once exceeded (Linux only)
+    ///
+    /// Only the soft limit is set, as a hard limit could not be raised again as the
+    /// budget grows. The command may raise its own soft limit up to the hard one, so this
+    /// guards against runaway commands rather than hostile ones; use --child-cpu-cgroup
+    /// for a limit the command cannot lift.
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "child-cpu-limit",
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "attach"
+    )]
+    child_cpu_limit: Option<u32>,
+
+    /// Throttle the command with the cpu.max of this cgroup v2 instead of killing it
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "child-cpu-cgroup",
+        value_name = "DIR",
+        requires = "child_cpu_limit"
+    )]
+    child_cpu_cgroup: Option<PathBuf>,
+
+    /// Only allow the command the syscalls listed in a JSON seccomp profile
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    #[arg(
+        long = "child-seccomp-profile",
+        value_name = "PATH",
+        conflicts_with = "attach"
+    )]
+    child_seccomp_profile: Option<PathBuf>,
+
+    /// Run the command under `perf record -o PATH` and print `perf report` to stderr at exit
+    #[arg(long = "perf-record", value_name = "PATH", conflicts_with = "attach")]
+    perf_record: Option<PathBuf>,
+
+    /// Run the command under a valgrind tool, writing its results to --valgrind-output
+    #[arg(
+        long = "valgrind",
+        value_name = "TOOL",
+        requires = "valgrind_output",
+        conflicts_with_all = ["attach", "perf_record"]
+    )]
+    valgrind: Option<profiling::ValgrindTool>,
+
+    /// Write the results of --valgrind to PATH (XML for memcheck and helgrind), and its messages to PATH.log
+    #[arg(long = "valgrind-output", value_name = "PATH", requires = "valgrind")]
+    valgrind_output: Option<PathBuf>,
+
+    /// Trace the command with ptrace and log its writes to stdout and stderr (x86_64 Linux only)
+    #[cfg(debug_ptrace)]
+    #[arg(
+        long = "debug-ptrace",
+        conflicts_with_all = ["attach", "perf_record", "valgrind"]
+    )]
+    debug_ptrace: bool,
+
+    /// Pass STRING as argv[0] of the command, e.g. to pick the personality of a multi-call binary
+    ///
+    /// Not available with --perf-record and --valgrind, whose tools would get it instead
+    /// of the command they run.
+    #[arg(
+        long = "child-argv0",
+        value_name = "STRING",
+        conflicts_with_all = ["attach", "perf_record", "valgrind"]
+    )]
+    child_argv0: Option<String>,
+
+    /// Change the mode of the PTY slave device, e.g. 0666 for unprivileged access in containers
+    #[arg(
+        long = "pty-slave-chmod",
+        value_name = "OCTAL_MODE",
+        value_parser = pty_slave::parse_mode,
+        conflicts_with = "attach"
+    )]
+    pty_slave_chmod: Option<u32>,
+
+    /// Change the owner of the PTY slave device, as names or numeric IDs
+    #[arg(
+        long = "pty-slave-chown",
+        value_name = "USER:GROUP",
+        value_parser = pty_slave::parse_owner,
+        conflicts_with = "attach"
+    )]
+    pty_slave_chown: Option<(nix::unistd::Uid, nix::unistd::Gid)>,
+
+    /// Disable echo on the PTY of the command
+    #[arg(
+        long = "no-echo",
+        overrides_with = "force_echo",
+        conflicts_with = "attach"
+    )]
+    no_echo: bool,
+
+    /// Enable echo on the PTY of the command, whatever its default
+    #[arg(
+        long = "force-echo",
+        overrides_with = "no_echo",
+        conflicts_with = "attach"
+    )]
+    force_echo: bool,
+
+    /// Accept input to inject from `cli-keyhook send` on a Unix domain socket
+    #[arg(long = "control-socket", value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+
+    /// Receive an fd with SCM_RIGHTS on a Unix domain socket and read it as additional input
+    #[arg(long = "fd-passing", value_name = "PATH")]
+    fd_passing: Option<PathBuf>,
+
+    /// Type the text of QR codes seen by a V4L2 camera, decoded with zbarcam
+    #[arg(long = "input-from-qr", value_name = "DEVICE")]
+    input_from_qr: Option<PathBuf>,
+
+    /// Type keystrokes for the notes played on an ALSA sequencer port, read with aseqdump
+    #[arg(
+        long = "input-from-midi",
+        value_name = "PORT",
+        requires = "midi_keymaps"
+    )]
+    input_from_midi: Option<String>,
+
+    /// Keystrokes (hex format) typed when a MIDI note (0-127) is played
+    #[arg(long = "midi-keymap", value_name = "NOTE:HEX", value_parser = midi::parse_midi_keymap)]
+    midi_keymaps: Vec<(u8, Vec<u8>)>,
+
+    /// Write each output segment as a QR code image PATH.<n>.png, encoded with qrencode
+    #[arg(long = "output-to-qr", value_name = "PATH")]
+    output_to_qr: Option<PathBuf>,
+
+    /// Delimiter (hex format) of the output segments written by --output-to-qr
+    #[arg(long = "output-split", value_name = "HEX", default_value = "0d0a")]
+    output_split: String,
+
+    /// Also write output lines matching the regex PATTERN to the stdin of COMMAND started at launch
+    #[arg(
+        long = "output-stream-split",
+        value_name = "PATTERN:COMMAND",
+        value_parser = stream_split::parse_split
+    )]
+    output_stream_splits: Vec<(regex::bytes::Regex, String)>,
+
+    /// Also read input from a serial DEVICE, e.g. a keyboard or terminal on a serial line
+    #[arg(long = "input-from-serial", value_name = "DEVICE")]
+    input_from_serial: Option<PathBuf>,
+
+    /// Also write the output to a serial DEVICE, e.g. to show it on a hardware terminal
+    #[arg(long = "output-to-serial", value_name = "DEVICE")]
+    output_to_serial: Option<PathBuf>,
+
+    /// Baud rate of --input-from-serial and --output-to-serial
+    #[arg(
+        long = "serial-baud",
+        value_name = "N",
+        default_value = "9600",
+        value_parser = serial::parse_baud
+    )]
+    serial_baud: BaudRate,
+
+    /// Paste the clipboard content (from wl-paste, xclip or pbpaste) into the PTY at startup
+    #[arg(long = "input-from-clipboard")]
+    input_from_clipboard: bool,
+
+    /// Copy the output of the command to the clipboard (with wl-copy, xclip or pbcopy) at exit
+    #[arg(long = "output-to-clipboard")]
+    output_to_clipboard: bool,
+
+    /// Most bytes copied by --output-to-clipboard; earlier output is dropped
+    #[arg(long = "clipboard-max-bytes", value_name = "BYTES", default_value_t = 1 << 20)]
+    clipboard_max_bytes: usize,
+
+    /// Exit with an error if no keymap is configured
+    #[arg(long = "fail-on-empty-keymap", overrides_with = "warn_on_empty_keymap")]
+    fail_on_empty_keymap: bool,
+
+    /// Print a warning if no keymap is configured, but proceed (default)
+    #[arg(long = "warn-on-empty-keymap", overrides_with = "fail_on_empty_keymap")]
+    warn_on_empty_keymap: bool,
+
+    /// How bytes typed by the user are interpreted before keymap processing
+    #[arg(long = "input-codec", value_name = "CODEC", default_value = "utf8")]
+    input_codec: Codec,
+
+    /// How bytes written by the command are interpreted before reaching the terminal
+    #[arg(long = "output-codec", value_name = "CODEC", default_value = "binary")]
+    output_codec: Codec,
+
+    /// Replace a character typed by the user before keymaps are applied
+    #[arg(long = "input-char-map", value_name = "FROM_CHAR:TO_CHAR", value_parser = charmap::parse_char_map)]
+    input_char_maps: Vec<(char, char)>,
+
+    /// Replace a character in the output of the command, leaving escape sequences alone
+    #[arg(long = "output-char-map", value_name = "FROM_CHAR:TO_CHAR", value_parser = charmap::parse_char_map)]
+    output_char_maps: Vec<(char, char)>,
+
+    /// Run a shell command in the background for every chunk of input
+    #[arg(long = "input-event-hook", value_name = "COMMAND")]
+    input_event_hook: Option<String>,
+
+    /// Remap every Nth occurrence of KEY to OUTPUT (hex format)
+    #[arg(long = "remap-on-nth", value_names = ["KEY", "N", "OUTPUT"], num_args = 3)]
+    remap_on_nth: Vec<String>,
+
+    /// Remap the Nth and all later occurrences of KEY to OUTPUT (hex format)
+    #[arg(long = "remap-after-nth", value_names = ["KEY", "N", "OUTPUT"], num_args = 3)]
+    remap_after_nth: Vec<String>,
+
+    /// Run a shell command in the background whenever KEY (hex format) is matched
+    #[arg(long = "on-match-exec", value_names = ["KEY", "COMMAND"], num_args = 2)]
+    on_match_exec: Vec<String>,
+
+    /// Track the typing rate and pass it to --input-event-hook as $CLI_KEYHOOK_INPUT_RATE_BPS
+    #[arg(long = "input-rate-estimator", value_name = "ESTIMATOR")]
+    input_rate_estimator: Option<RateEstimator>,
+
+    /// Longest wait for input before checking whether the command exited
+    #[arg(
+        long = "select-timeout",
+        value_name = "MS",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    select_timeout: u64,
+
+    /// Size of the buffer for reads from stdin (a power of 2 from 16 to 1048576)
+    #[arg(
+        long = "input-buffer-size",
+        value_name = "BYTES",
+        default_value_t = DEFAULT_INPUT_BUFFER_SIZE,
+        value_parser = |s: &str| parse_buffer_size(s, 16)
+    )]
+    input_buffer_size: usize,
+
+    /// Size of the buffer for reads from the PTY master (a power of 2 from 64 to 1048576)
+    #[arg(
+        long = "pty-buffer-size",
+        value_name = "BYTES",
+        default_value_t = DEFAULT_PTY_BUFFER_SIZE,
+        value_parser = |s: &str| parse_buffer_size(s, 64)
+    )]
+    pty_buffer_size: usize,
+
+    /// Use select for the event loop (default on Linux)
+    #[arg(long = "select", group = "backend")]
+    select: bool,
+
+    /// Use epoll instead of select for the event loop (Linux only)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "epoll", group = "backend")]
+    epoll: bool,
+
+    /// Use io_uring instead of select for the event loop (Linux 5.1+)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "io-uring", group = "backend")]
+    io_uring: bool,
+
+    /// Use kqueue for the event loop (default on macOS and BSD)
+    #[cfg(kqueue)]
+    #[arg(long = "kqueue", group = "backend")]
+    kqueue: bool,
+
+    /// Print a hex dump line for every read from stdin instead of running a command
+    #[arg(long = "input-hex-dump-only", conflicts_with_all = ["command", "args"])]
+    #[cfg_attr(target_os = "linux", arg(conflicts_with = "attach"))]
+    input_hex_dump_only: bool,
+
+    /// Print a hex dump line for every read from the PTY instead of the output of the command
+    #[arg(long = "output-hex-dump-only")]
+    output_hex_dump_only: bool,
+
     /// Command to execute
-    command: String,
+    #[cfg_attr(
+        target_os = "linux",
+        arg(required_unless_present_any = ["attach", "input_hex_dump_only"])
+    )]
+    #[cfg_attr(
+        not(target_os = "linux"),
+        arg(required_unless_present = "input_hex_dump_only")
+    )]
+    command: Option<String>,
 
     /// Arguments for the command
     args: Vec<String>,
 }
 
+/// Which processor chains a `--keymap` entry is added to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum KeymapScope {
+    /// Only remap user input
+    InputOnly,
+    /// Only remap the output of the command
+    OutputOnly,
+    /// Remap both directions
+    Both,
+}
+
+/// Sub-commands interacting with a running wrapper.
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Send bytes to a wrapper started with --control-socket, as if typed
+    Send {
+        /// Path of the control socket
+        #[arg(long = "socket", value_name = "PATH")]
+        socket: PathBuf,
+
+        /// Bytes to send (hex format)
+        #[arg(long = "bytes", value_name = "HEX", value_parser = hex_decode)]
+        bytes: ::std::vec::Vec<u8>,
+    },
+}
+
+impl Args {
+    /// Returns the event loop backend selected on the command line.
+    fn backend(&self) -> Backend {
+        #[cfg(target_os = "linux")]
+        if self.io_uring {
+            return Backend::IoUring;
+        }
+        #[cfg(target_os = "linux")]
+        if self.epoll {
+            return Backend::Epoll;
+        }
+        #[cfg(kqueue)]
+        if self.kqueue {
+            return Backend::Kqueue;
+        }
+        if self.select {
+            return Backend::Select;
+        }
+        Backend::default()
+    }
+}
+
 /// Main entry point for the CLI key hook program.
 ///
 /// Parses command line arguments, sets up key mappings, and runs the PTY wrapper.
 fn main() -> Result<()> {
-    let args = Args::parse();
+    // Aliases are substituted before clap parses the keymaps they occur in
+    let matches = Args::command().get_matches_from(alias::expand_args(
+        &Args::command(),
+        std::env::args_os().collect(),
+    )?);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    if let Some(Subcommand::Send { socket, bytes }) = &args.subcommand {
+        return control::send(socket, bytes);
+    }
+    if args.input_hex_dump_only {
+        return hex_dump::dump_input(args.input_buffer_size);
+    }
+
+    let backend = args.backend();
+
+    let mut keymap = KeyMap::new();
+    for term_name in &args.keymap_from_xterm_terminfo {
+        keymap.extend(import::keymaps_from_terminfo(term_name)?);
+    }
+    for path in &args.keymap_from_paste_history {
+        keymap.extend(import::keymaps_from_paste_history(path)?);
+    }
+    for path in &args.keymap_from_xdotool_script {
+        keymap.extend(import::keymaps_from_xdotool_script(path)?);
+    }
+    for path in &args.keymap_from_vscode {
+        keymap.extend(import::keymaps_from_vscode(path)?);
+    }
+    keymap.extend(import::keymaps_from_fusuma(
+        &args.keymap_from_fusuma,
+        &args.fusuma_gestures,
+    )?);
+    for var in &args.keymap_from_env {
+        keymap.extend(keymaps_from_env(var));
+    }
+    let (input_keymap, output_keymap) =
+        split_keymaps_by_scope(&matches, std::mem::take(&mut args.keymaps));
+    keymap.extend(input_keymap);
+
+    let mut counted_remaps = parse_counted_remaps(&args.remap_on_nth, Occurrence::EveryNth)?;
+    counted_remaps.extend(parse_counted_remaps(
+        &args.remap_after_nth,
+        Occurrence::FromNth,
+    )?);
+
+    // Every option remapping input or output, or typing input of its own, counts
+    if keymap.is_empty()
+        && output_keymap.is_empty()
+        && counted_remaps.is_empty()
+        && args.input_char_maps.is_empty()
+        && args.output_char_maps.is_empty()
+        && args.on_match_exec.is_empty()
+        && args.lock_key.is_none()
+        && args.midi_keymaps.is_empty()
+        && args.input_from_qr.is_none()
+    {
+        if args.fail_on_empty_keymap {
+            anyhow::bail!("no keymap is configured (see --keymap)");
+        }
+        eprintln!(
+            "cli-keyhook: warning: no keymap is configured, input is passed through unchanged"
+        );
+    }
+
+    #[cfg(feature = "memory-profile")]
+    let profiler = args
+        .memory_profile
+        .as_deref()
+        .map(|path| {
+            memory_profile::MemoryProfiler::start(
+                path,
+                std::time::Duration::from_secs(args.memory_profile_interval),
+            )
+        })
+        .transpose()?;
+
+    #[cfg(target_os = "linux")]
+    let attach = args.attach;
+    #[cfg(not(target_os = "linux"))]
+    let attach: Option<i32> = None;
+
+    let on_match_exec = args
+        .on_match_exec
+        .chunks(2)
+        .map(|pair| {
+            let key = hex_decode(&pair[0])
+                .map_err(|e| anyhow::anyhow!("invalid --on-match-exec key '{}' ({e})", pair[0]))?;
+            Ok((key, pair[1].clone()))
+        })
+        .collect::<Result<_>>()?;
+
+    let config = ParentConfig {
+        keymap,
+        lock_key: args
+            .lock_key
+            .map(|key| (key, lock::RemapLock::new(args.input_mode_indicator))),
+        backend,
+        wait_timeout: Duration::from_millis(args.select_timeout),
+        input_buffer_size: args.input_buffer_size,
+        pty_buffer_size: args.pty_buffer_size,
+        control_socket: args.control_socket,
+        fd_passing: args.fd_passing,
+        input_from_qr: args.input_from_qr,
+        input_from_midi: args.input_from_midi,
+        midi_keymap: args.midi_keymaps.into_iter().collect(),
+        output_to_qr: args.output_to_qr,
+        input_from_serial: args.input_from_serial,
+        output_to_serial: args.output_to_serial,
+        serial_baud: args.serial_baud,
+        output_stream_splits: args.output_stream_splits,
+        output_split: hex_decode(&args.output_split).map_err(|e| {
+            anyhow::anyhow!(
+                "invalid --output-split delimiter '{}' ({e})",
+                args.output_split
+            )
+        })?,
+        clipboard_input: args
+            .input_from_clipboard
+            .then(clipboard::paste)
+            .transpose()?,
+        output_to_clipboard: args.output_to_clipboard.then_some(args.clipboard_max_bytes),
+        byte_swap: args.keymap_byte_swap,
+        input_codec: args.input_codec,
+        input_char_map: args.input_char_maps.into_iter().collect(),
+        output_codec: args.output_codec,
+        output_char_map: args.output_char_maps.into_iter().collect(),
+        output_keymap,
+        output_hex_dump_only: args.output_hex_dump_only,
+        #[cfg(target_os = "linux")]
+        rss_limit: args.child_rss_limit.map(|mb| rss_limit::RssLimit {
+            max_kib: mb * 1024,
+            interval: Duration::from_millis(args.rss_check_interval),
+        }),
+        #[cfg(target_os = "linux")]
+        cpu_budget: args
+            .child_cpu_limit
+            .filter(|_| args.child_cpu_cgroup.is_none()),
+        #[cfg(debug_ptrace)]
+        debug_ptrace: args.debug_ptrace,
+        input_rate_estimator: args.input_rate_estimator,
+        input_event_hook: args.input_event_hook,
+        on_match_exec,
+        counted_remaps,
+    };
+    let child_config = ChildConfig {
+        argv0: args.child_argv0,
+        slave_mode: args.pty_slave_chmod,
+        slave_owner: args.pty_slave_chown,
+        echo: match (args.no_echo, args.force_echo) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None,
+        },
+        #[cfg(target_os = "linux")]
+        cpu_set: affinity::cpu_set(args.child_affinity_strategy)?,
+        #[cfg(target_os = "linux")]
+        cpu_cgroup: match (args.child_cpu_limit, args.child_cpu_cgroup) {
+            (Some(percent), Some(cgroup)) => {
+                cpu_limit::set_cpu_max(&cgroup, percent)?;
+                Some(cgroup)
+            }
+            _ => None,
+        },
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        seccomp_filter: args
+            .child_seccomp_profile
+            .as_deref()
+            .map(seccomp::load_profile)
+            .transpose()?,
+    };
+
+    let mut command_line = args.command.map(|command| (command, args.args));
+    let mut perf_output = None;
+    if let Some((command, command_args)) = &mut command_line {
+        if let Some(output) = args.perf_record {
+            if let Some(perf_command_line) = profiling::perf_record(&output, command, command_args)
+            {
+                (*command, *command_args) = perf_command_line;
+                perf_output = Some(output);
+            }
+        } else if let (Some(tool), Some(output)) = (args.valgrind, &args.valgrind_output) {
+            if let Some(valgrind_command_line) =
+                profiling::valgrind(tool, output, command, command_args)
+            {
+                (*command, *command_args) = valgrind_command_line;
+            }
+        }
+    }
+
+    let result = match (attach, command_line) {
+        #[cfg(target_os = "linux")]
+        (Some(pid), _) => run_attached(Pid::from_raw(pid), &config),
+        (_, Some((command, command_args))) => {
+            run_pty_wrapper(&command, &command_args, &config, &child_config)
+        }
+        _ => unreachable!("clap requires either COMMAND or --attach"),
+    };
+
+    if let (Ok(()), Some(output)) = (&result, perf_output) {
+        profiling::perf_report(&output)?;
+    }
 
-    run_pty_wrapper(&args.command, &args.args, KeyMap::from_iter(args.keymaps))
+    // Take a last snapshot so short sessions still produce at least one profile
+    #[cfg(feature = "memory-profile")]
+    if let Some(profiler) = profiler {
+        profiler.dump()?;
+    }
+
+    result
 }
 
 /// Parses a keymap string in the format "input_hex:output_hex".
@@ -72,6 +1029,126 @@ fn parse_keymap(s: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
     Ok((input_bytes, output_bytes))
 }
 
+/// Splits `--keymap` entries by the `--keymap-scope` preceding each of them.
+///
+/// # Arguments
+/// * `matches` - Parsed command line, for the positions of the flags
+/// * `keymaps` - The `--keymap` entries, in command line order
+///
+/// # Returns
+/// The keymaps for the input and the output direction
+fn split_keymaps_by_scope(
+    matches: &ArgMatches,
+    keymaps: Vec<(Vec<u8>, Vec<u8>)>,
+) -> (KeyMap, KeyMap) {
+    let scopes: Vec<(usize, KeymapScope)> = matches
+        .indices_of("keymap_scopes")
+        .into_iter()
+        .flatten()
+        .zip(
+            matches
+                .get_many::<KeymapScope>("keymap_scopes")
+                .into_iter()
+                .flatten()
+                .copied(),
+        )
+        .collect();
+    let positions = matches.indices_of("keymaps").into_iter().flatten();
+
+    let mut input = KeyMap::new();
+    let mut output = KeyMap::new();
+    for (position, (key, mapped)) in positions.zip(keymaps) {
+        let scope = scopes
+            .iter()
+            .take_while(|(index, _)| *index < position)
+            .last()
+            .map_or(KeymapScope::InputOnly, |&(_, scope)| scope);
+
+        if scope != KeymapScope::InputOnly {
+            output.insert(key.clone(), mapped.clone());
+        }
+        if scope != KeymapScope::OutputOnly {
+            input.insert(key, mapped);
+        }
+    }
+
+    (input, output)
+}
+
+/// Parses the KEY N OUTPUT triples of `--remap-on-nth` or `--remap-after-nth`.
+///
+/// # Arguments
+/// * `values` - Flattened triples as collected by clap
+/// * `occurrence` - Which occurrences the remaps apply to
+///
+/// # Returns
+/// * `Ok(remaps)` on success
+/// * `Err(error)` on an invalid key, count or output
+fn parse_counted_remaps(values: &[String], occurrence: Occurrence) -> Result<Vec<CountedRemap>> {
+    values
+        .chunks(3)
+        .map(|triple| {
+            let (input_bytes, output_bytes) = parse_keymap(&format!("{}:{}", triple[0], triple[2]))
+                .map_err(anyhow::Error::msg)?;
+            let n = triple[1].parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                anyhow::anyhow!("invalid count '{}', expected a positive integer", triple[1])
+            })?;
+            Ok(CountedRemap {
+                key: input_bytes,
+                n,
+                output: output_bytes,
+                occurrence,
+            })
+        })
+        .collect()
+}
+
+/// Parses a buffer size, which must be a power of 2 from `min` to 1048576.
+///
+/// # Arguments
+/// * `s` - Size in bytes
+/// * `min` - Smallest accepted size
+///
+/// # Returns
+/// * `Ok(size)` on success
+/// * `Err(error_message)` on an invalid size
+fn parse_buffer_size(s: &str, min: usize) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if size.is_power_of_two() && (min..=1048576).contains(&size) => Ok(size),
+        _ => Err(format!(
+            "invalid buffer size '{s}', expected a power of 2 from {min} to 1048576"
+        )),
+    }
+}
+
+/// Reads keymaps from an environment variable in the format "in1:out1;in2:out2".
+///
+/// An unset variable yields no keymaps. Malformed entries are skipped with a warning.
+///
+/// # Arguments
+/// * `var` - Name of the environment variable
+///
+/// # Returns
+/// The keymaps which could be parsed
+fn keymaps_from_env(var: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let Some(value) = std::env::var_os(var) else {
+        return Vec::new();
+    };
+    let value = value.to_string_lossy();
+
+    value
+        .split(';')
+        .filter(|segment| !segment.is_empty())
+        .filter_map(|segment| match parse_keymap(segment) {
+            Ok(keymap) => Some(keymap),
+            Err(e) => {
+                eprintln!("cli-keyhook: warning: ignoring '{segment}' in ${var}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
 /// Decodes a hexadecimal string into a vector of bytes.
 ///
 /// # Arguments
@@ -103,30 +1180,132 @@ fn hex_decode(hex_str: &str) -> Result<Vec<u8>, String> {
     Ok(bytes)
 }
 
+/// Encodes bytes as a lowercase hexadecimal string, the inverse of `hex_decode`.
+///
+/// # Arguments
+/// * `bytes` - Bytes to encode
+///
+/// # Returns
+/// Two hex digits per byte
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Writes all of `bytes` to the pipe read by the event loop, see [`Source::Injected`].
+///
+/// # Arguments
+/// * `injector` - Write end of the pipe
+/// * `bytes` - Input to process like stdin
+fn inject(injector: impl AsFd, bytes: &[u8]) -> nix::Result<()> {
+    let mut written = 0;
+    while written < bytes.len() {
+        match nix::unistd::write(&injector, &bytes[written..]) {
+            Ok(n) => written += n,
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Starts an external tool, saying what to install if it is not found.
+///
+/// # Arguments
+/// * `command` - Command running the tool, ready to spawn
+/// * `package` - What provides the tool, for the error message
+fn spawn_tool(command: &mut std::process::Command, package: &str) -> Result<std::process::Child> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command.spawn().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => {
+            anyhow::anyhow!("'{program}' was not found in PATH, install {package} to use it")
+        }
+        _ => anyhow::Error::new(e).context(format!("failed to run '{program}'")),
+    })
+}
+
 /// Runs the main PTY wrapper that forks into parent and child processes.
 ///
 /// # Arguments
 /// * `command` - The command to execute in the child process
 /// * `args` - Arguments for the command
-/// * `keymap` - Key mapping configuration for input transformation
-fn run_pty_wrapper(command: &str, args: &[String], keymap: KeyMap) -> Result<()> {
+/// * `config` - Settings of the parent process
+/// * `child_config` - Settings of the child process
+fn run_pty_wrapper(
+    command: &str,
+    args: &[String],
+    config: &ParentConfig,
+    child_config: &ChildConfig,
+) -> Result<()> {
     let winsize = get_terminal_size()?;
     let pty = nix::pty::openpty(&winsize, None)?;
 
     let master = pty.master;
     let slave = pty.slave;
+    // Keep the master away from commands spawned by hooks and splits, which would
+    // otherwise hold the PTY open
+    nix::fcntl::fcntl(
+        &master,
+        nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+    )?;
+
+    if let Some(mode) = child_config.slave_mode {
+        pty_slave::chmod(&slave, mode)?;
+    }
+    if let Some(owner) = child_config.slave_owner {
+        pty_slave::chown(&slave, owner)?;
+    }
+    if let Some(echo) = child_config.echo {
+        let mut termios = termios::tcgetattr(&slave)?;
+        termios
+            .local_flags
+            .set(LocalFlags::ECHO | LocalFlags::ECHOE, echo);
+        termios::tcsetattr(&slave, termios::SetArg::TCSANOW, &termios)?;
+    }
+    let exec = Exec::new(command, args, child_config)?;
 
     let original_termios = save_terminal_settings()?;
+    #[cfg(debug_ptrace)]
+    let gate = config.debug_ptrace.then(debug_ptrace::gate).transpose()?;
 
-    // SAFETY: only `close` and `dup2` are called before child's `execvp`.
+    // SAFETY: no other thread runs at this point, except the sampler of
+    // `--memory-profile`, whose allocator is fork-safe. Before `execvp`, the child
+    // still only closes and duplicates fds, waits on the `--debug-ptrace` gate, and
+    // makes the system calls of `--child-affinity-strategy` and `--child-cpu-cgroup`
+    // on what was prepared above. Only loading the `--seccomp-profile` filter, which
+    // libseccomp compiles then, and reporting errors, which end the child, allocate.
     match unsafe { nix::unistd::fork() }? {
         ForkResult::Parent { child } => {
-            drop(slave); // Close slave fd
+            // Close the parent's copy of the slave explicitly instead of relying on `drop`,
+            // which would silently become a no-op should `openpty` ever return a raw fd.
+            // While the parent holds the slave open, the PTY outlives the child: reads
+            // from the master never report the end of the output after the child exits.
+            nix::unistd::close(slave)?;
 
             setup_raw_mode()?;
-            setup_signal_handler(&master)?;
+            let master = Arc::new(master);
+            let resize_forwarder = if config.backend.handles_resize() {
+                None
+            } else {
+                Some(setup_signal_handler(&master)?)
+            };
+
+            #[cfg(debug_ptrace)]
+            let child = match gate {
+                Some((read_end, write_end)) => {
+                    drop(read_end);
+                    traced(child, write_end)
+                }
+                None => Child::Forked(child),
+            };
+            #[cfg(not(debug_ptrace))]
+            let child = Child::Forked(child);
+            let result = parent_process(&master, child, config);
 
-            let result = parent_process(master, child, keymap);
+            // Stop forwarding resizes before the master fd is closed
+            if let Some(forwarder) = resize_forwarder {
+                let _ = forwarder.stop();
+            }
+            drop(master); // Close master fd
 
             restore_terminal_settings(&original_termios)?;
 
@@ -134,8 +1313,125 @@ fn run_pty_wrapper(command: &str, args: &[String], keymap: KeyMap) -> Result<()>
         }
         ForkResult::Child => {
             drop(master); // Close master fd
-            child_process(slave, command, args)
+            #[cfg(debug_ptrace)]
+            if let Some((read_end, write_end)) = gate {
+                drop(write_end);
+                debug_ptrace::wait_for_tracer(read_end);
+            }
+            child_process(slave, &exec, child_config)
+        }
+    }
+}
+
+/// Starts tracing a forked child for `--debug-ptrace`.
+///
+/// Tracing is a debugging aid, so the command keeps running untraced if it fails.
+///
+/// # Arguments
+/// * `pid` - Process ID of the child
+/// * `gate` - Write end of the pipe the child waits on before `execvp`
+#[cfg(debug_ptrace)]
+fn traced(pid: Pid, gate: OwnedFd) -> Child {
+    match debug_ptrace::trace_writes(pid, gate) {
+        Ok(()) => Child::Traced(pid),
+        Err(e) => {
+            eprintln!("cli-keyhook: warning: {e:#}");
+            Child::Forked(pid)
+        }
+    }
+}
+
+/// Attaches key remapping to the PTY of an already-running process.
+///
+/// Takes a duplicate of the PTY master held by the process that allocated it
+/// and runs the usual parent process loop until stdin closes or the process exits.
+/// The attached TTY settings and size are restored on detach.
+///
+/// # Arguments
+/// * `pid` - Process ID of the running process
+/// * `config` - Settings of the parent process
+#[cfg(target_os = "linux")]
+fn run_attached(pid: Pid, config: &ParentConfig) -> Result<()> {
+    let master = attach::open_master(pid)?;
+
+    let attached_termios = termios::tcgetattr(&master)?;
+    let mut attached_winsize = get_terminal_size()?;
+    unsafe {
+        nix::libc::ioctl(
+            master.as_raw_fd(),
+            nix::libc::TIOCGWINSZ,
+            &mut attached_winsize,
+        );
+    }
+
+    let original_termios = save_terminal_settings()?;
+
+    setup_raw_mode()?;
+    let master = Arc::new(master);
+    let resize_forwarder = if config.backend.handles_resize() {
+        None
+    } else {
+        Some(setup_signal_handler(&master)?)
+    };
+
+    // Adopt the size of the current terminal, as on SIGWINCH
+    let _ = forward_terminal_size(master.as_raw_fd());
+
+    let result = parent_process(&master, Child::Attached(pid), config);
+
+    if let Some(forwarder) = resize_forwarder {
+        let _ = forwarder.stop();
+    }
+
+    restore_terminal_settings(&original_termios)?;
+    termios::tcsetattr(&*master, termios::SetArg::TCSANOW, &attached_termios)?;
+    unsafe {
+        nix::libc::ioctl(master.as_raw_fd(), nix::libc::TIOCSWINSZ, &attached_winsize);
+    }
+
+    result
+}
+
+/// The process on the other side of the PTY served by `parent_process`.
+#[derive(Clone, Copy)]
+enum Child {
+    /// A child forked by `run_pty_wrapper`, reaped with `waitpid`.
+    Forked(Pid),
+    /// A process attached with `--attach`, which is not our child and cannot be reaped.
+    #[cfg(target_os = "linux")]
+    Attached(Pid),
+    /// A forked child traced with `--debug-ptrace`, reaped by the tracer thread.
+    #[cfg(debug_ptrace)]
+    Traced(Pid),
+}
+
+impl Child {
+    /// Checks without blocking whether the process has exited.
+    fn has_exited(self) -> bool {
+        match self {
+            Child::Forked(pid) => matches!(
+                nix::sys::wait::waitpid(pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG)),
+                Ok(status) if status != WaitStatus::StillAlive
+            ),
+            #[cfg(target_os = "linux")]
+            Child::Attached(pid) => nix::sys::signal::kill(pid, None).is_err(),
+            #[cfg(debug_ptrace)]
+            Child::Traced(_) => debug_ptrace::has_exited(),
+        }
+    }
+
+    /// Waits for a forked child to exit; an attached process is simply left running.
+    fn wait(self) -> Result<()> {
+        match self {
+            Child::Forked(pid) => {
+                nix::sys::wait::waitpid(pid, None)?;
+            }
+            #[cfg(target_os = "linux")]
+            Child::Attached(_) => {}
+            #[cfg(debug_ptrace)]
+            Child::Traced(_) => debug_ptrace::wait(),
         }
+        Ok(())
     }
 }
 
@@ -146,101 +1442,278 @@ fn run_pty_wrapper(command: &str, args: &[String], keymap: KeyMap) -> Result<()>
 ///
 /// # Arguments
 /// * `master` - PTY master file descriptor
-/// * `child_pid` - Process ID of the child process
-/// * `keymap` - Key mapping configuration
-fn parent_process(master: OwnedFd, child_pid: Pid, keymap: KeyMap) -> Result<()> {
+/// * `child` - The process connected to the PTY slave
+/// * `config` - Settings of the parent process
+fn parent_process(master: &OwnedFd, child: Child, config: &ParentConfig) -> Result<()> {
     let stdin = io::stdin();
     let stdout = io::stdout();
 
-    let mut buffer = [0u8; 16384];
-    let mut child_exited = false;
+    // Input of the control socket, QR codes, MIDI notes and the clipboard goes through the
+    // event loop, so that it is processed like stdin; the loop sees the end once they all
+    // stopped
+    let (injected, injector) = nix::unistd::pipe()?;
+    for end in [&injected, &injector] {
+        // Keep the pipe away from commands spawned by hooks
+        nix::fcntl::fcntl(
+            end,
+            nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+        )?;
+    }
+    // Removes the socket path again when the session ends
+    let _control_socket = config
+        .control_socket
+        .as_deref()
+        .map(|path| control::ControlSocket::bind(path, injector.try_clone()?))
+        .transpose()?;
+    let _qr_scanner = config
+        .input_from_qr
+        .as_deref()
+        .map(|device| qr::QrScanner::spawn(device, injector.try_clone()?))
+        .transpose()?;
+    let _midi_listener = config
+        .input_from_midi
+        .as_deref()
+        .map(|port| {
+            midi::MidiListener::spawn(port, config.midi_keymap.clone(), injector.try_clone()?)
+        })
+        .transpose()?;
+    // Written from a thread, as a long paste may fill the pipe before the loop reads it
+    if let Some(pasted) = config.clipboard_input.clone() {
+        let injector = injector.try_clone()?;
+        thread::spawn(move || {
+            let _ = inject(&injector, &pasted);
+        });
+    }
+    drop(injector);
+    // Stopped as soon as the session ends, as the PID of a reaped child may be reused
+    #[cfg(target_os = "linux")]
+    let rss_guard = match (child, config.rss_limit) {
+        (Child::Forked(pid), Some(limit)) => Some(rss_limit::RssGuard::spawn(pid, limit)),
+        #[cfg(debug_ptrace)]
+        (Child::Traced(pid), Some(limit)) => Some(rss_limit::RssGuard::spawn(pid, limit)),
+        _ => None,
+    };
+    #[cfg(target_os = "linux")]
+    let cpu_budget = match (child, config.cpu_budget) {
+        (Child::Forked(pid), Some(percent)) => Some(cpu_limit::CpuBudget::spawn(pid, percent)),
+        #[cfg(debug_ptrace)]
+        (Child::Traced(pid), Some(percent)) => Some(cpu_limit::CpuBudget::spawn(pid, percent)),
+        _ => None,
+    };
+    let passed = config
+        .fd_passing
+        .as_deref()
+        .map(fd_passing::receive_fd)
+        .transpose()?;
+    let serial_input = config
+        .input_from_serial
+        .as_deref()
+        .map(|device| serial::open(device, config.serial_baud))
+        .transpose()?;
 
-    loop {
-        let mut read_fds = FdSet::new();
-        read_fds.insert(stdin.as_fd());
-        read_fds.insert(master.as_fd());
-
-        let mut timeout = TimeVal::new(0, 100_000); // 100ms
-
-        match nix::sys::select::select(
-            Some(std::cmp::max(stdin.as_raw_fd(), master.as_raw_fd()) + 1),
-            Some(&mut read_fds),
-            None,
-            None,
-            Some(&mut timeout),
-        ) {
-            Ok(n) => {
-                // Check child process status on every iteration
-                if let Ok(status) = nix::sys::wait::waitpid(child_pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG)) {
-                    if status != WaitStatus::StillAlive {
-                        child_exited = true;
-                        break;
-                    }
-                }
+    let mut inputs = vec![(Source::Stdin, stdin.as_fd())];
+    if let Some(passed) = &passed {
+        inputs.push((Source::Passed, passed.as_fd()));
+    }
+    if let Some(serial_input) = &serial_input {
+        inputs.push((Source::Serial, serial_input.as_fd()));
+    }
+    inputs.push((Source::Injected, injected.as_fd()));
 
-                if n != 0 {
-                    if read_fds.contains(stdin.as_fd()) {
-                        match nix::unistd::read(&stdin, &mut buffer) {
-                            Ok(0) => break,
-                            Ok(n) => {
-                                let processed_input = process_input_hook(&buffer[..n], &keymap);
-                                nix::unistd::write(&master, &processed_input)?;
-                            }
-                            Err(_) => continue,
-                        }
-                    }
-
-                    if read_fds.contains(master.as_fd()) {
-                        match nix::unistd::read(&master, &mut buffer) {
-                            Ok(0) => break,
-                            Ok(n) => {
-                                nix::unistd::write(&stdout, &buffer[..n])?;
-                            }
-                            Err(_) => continue,
-                        }
-                    }
-                }
-            }
-            Err(_) => continue,
+    let (mut input, mut output) = config.chains();
+    let capture = config.output_to_clipboard.map(|max_bytes| {
+        let capture = clipboard::Capture::default();
+        output.push(Box::new(clipboard::CaptureProcessor::new(
+            capture.clone(),
+            max_bytes,
+        )));
+        capture
+    });
+    let qr_encoder = config.output_to_qr.clone().map(|path| {
+        let (segments, encoder) = qr::spawn_encoder(path);
+        output.push(Box::new(qr::QrProcessor::new(
+            config.output_split.clone(),
+            segments,
+        )));
+        encoder
+    });
+    if !config.output_stream_splits.is_empty() {
+        let splits = config
+            .output_stream_splits
+            .iter()
+            .map(|(pattern, command)| Ok((pattern.clone(), stream_split::spawn_command(command)?)))
+            .collect::<Result<_>>()?;
+        output.push(Box::new(stream_split::StreamSplitProcessor::new(
+            splits,
+            config.pty_buffer_size,
+        )));
+    }
+    let serial_writer = match &config.output_to_serial {
+        Some(device) => {
+            let (sender, writer) = serial::spawn_writer(serial::open(device, config.serial_baud)?);
+            output.push(Box::new(serial::SerialProcessor::new(sender)));
+            Some(writer)
         }
+        None => None,
+    };
+    // Last, so that the processors copying the output above get it rather than its dump
+    if config.output_hex_dump_only {
+        output.push(Box::new(hex_dump::HexDumpProcessor));
     }
+    let child_exited = event_loop(
+        &inputs,
+        stdout.as_fd(),
+        master,
+        child,
+        &mut input,
+        &mut output,
+        config,
+    )?;
+    #[cfg(target_os = "linux")]
+    drop((rss_guard, cpu_budget));
 
     // Only call waitpid if child process hasn't exited yet
     if !child_exited {
-        nix::sys::wait::waitpid(child_pid, None)?;
+        child.wait()?;
+    }
+
+    // Sends the last segment to the encoder and lets it finish
+    drop(output);
+    if let Some(encoder) = qr_encoder {
+        let _ = encoder.join();
+    }
+    if let Some(writer) = serial_writer {
+        let _ = writer.join();
+    }
+
+    if let Some(capture) = capture {
+        clipboard::copy(&capture.take())?;
     }
-    drop(master); // Explicitly close master fd
 
     Ok(())
 }
 
+/// Runs the I/O loop between the user's terminal and the PTY master.
+///
+/// Forwards remapped input to the PTY and child output to stdout until either
+/// side reaches end of file or the child exits.
+///
+/// # Arguments
+/// * `inputs` - Where user input is read from: stdin, the fd received with `--fd-passing`
+///   and the `--input-from-serial` device
+/// * `stdout` - Where child output is written to
+/// * `master` - PTY master file descriptor
+/// * `child` - The process connected to the PTY slave
+/// * `input` - Processors applied to user input
+/// * `output` - Processors applied to child output
+/// * `config` - Settings of the parent process
+///
+/// # Returns
+/// Whether the loop ended because the child exited
+fn event_loop(
+    inputs: &[(Source, BorrowedFd)],
+    stdout: BorrowedFd,
+    master: &OwnedFd,
+    child: Child,
+    input: &mut Chain,
+    output: &mut Chain,
+    config: &ParentConfig,
+) -> Result<bool> {
+    let mut input_buffer = vec![0u8; config.input_buffer_size];
+    let mut output_buffer = vec![0u8; config.pty_buffer_size];
+
+    let mut sources = inputs.to_vec();
+    sources.push((Source::Master, master.as_fd()));
+    let mut poller = config.backend.create(&sources, |source| match source {
+        Source::Stdin | Source::Passed | Source::Serial | Source::Injected => {
+            config.input_buffer_size
+        }
+        Source::Master => config.pty_buffer_size,
+    })?;
+
+    loop {
+        let events = match poller.wait(config.wait_timeout) {
+            Ok(events) => events,
+            Err(_) => continue,
+        };
+
+        // Check child process status on every iteration
+        if child.has_exited() {
+            return Ok(true);
+        }
+
+        for event in events {
+            let source = match event {
+                Event::Readable(source) => source,
+                Event::Resized => {
+                    let _ = forward_terminal_size(master.as_raw_fd());
+                    continue;
+                }
+            };
+
+            let buffer = match source {
+                Source::Stdin | Source::Passed | Source::Serial | Source::Injected => {
+                    &mut input_buffer
+                }
+                Source::Master => &mut output_buffer,
+            };
+            match (source, poller.read(source, buffer)) {
+                // The session goes on without the additional input
+                (Source::Passed, Ok(0) | Err(_)) => poller.remove(Source::Passed),
+                (Source::Serial, Ok(0) | Err(_)) => poller.remove(Source::Serial),
+                (Source::Injected, Ok(0) | Err(_)) => poller.remove(Source::Injected),
+                (_, Ok(0)) => return Ok(false),
+                (Source::Stdin | Source::Passed | Source::Serial | Source::Injected, Ok(n)) => {
+                    let processed_input = input.process(&input_buffer[..n]);
+                    nix::unistd::write(master, &processed_input)?;
+                }
+                (Source::Master, Ok(n)) => {
+                    let processed_output = output.process(&output_buffer[..n]);
+                    nix::unistd::write(stdout, &processed_output)?;
+                }
+                (_, Err(_)) => break,
+            }
+        }
+    }
+}
+
 /// Handles the child process logic for command execution.
 ///
 /// Redirects stdin/stdout/stderr to the PTY slave and executes the specified command.
 ///
 /// # Arguments
 /// * `slave` - PTY slave file descriptor
-/// * `command` - Command to execute
-/// * `args` - Arguments for the command
-fn child_process(slave: OwnedFd, command: &str, args: &[String]) -> Result<()> {
+/// * `exec` - Command to execute, with its arguments
+/// * `child_config` - Settings applied before executing the command
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn child_process(slave: OwnedFd, exec: &Exec, child_config: &ChildConfig) -> Result<()> {
     nix::unistd::dup2_stdin(&slave)?;
     nix::unistd::dup2_stdout(&slave)?;
     nix::unistd::dup2_stderr(&slave)?;
 
     drop(slave); // Explicitly close slave fd
 
-    let cmd = CString::new(command)?;
-    let mut exec_args: Vec<CString> = vec![cmd.clone()];
-    for arg in args {
-        exec_args.push(CString::new(arg.as_str())?);
+    #[cfg(target_os = "linux")]
+    if let Some(cpu_set) = &child_config.cpu_set {
+        nix::sched::sched_setaffinity(Pid::from_raw(0), cpu_set)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(procs) = &exec.cgroup_procs {
+        cpu_limit::join_cgroup(procs)?;
+    }
+
+    // Load the filter last, so that only the command itself runs under it
+    #[cfg(all(target_os = "linux", feature = "seccomp"))]
+    if let Some(filter) = &child_config.seccomp_filter {
+        filter.load()?;
     }
 
-    nix::unistd::execvp(&cmd, &exec_args)?;
+    nix::unistd::execvp(&exec.command, &exec.args)?;
 
     Ok(())
 }
 
-/// Processes input bytes by applying key mappings.
+/// Processes input bytes by applying key mappings, reporting every matched keymap input.
 ///
 /// Scans the input for byte sequences that match keymap entries
 /// and replaces them with their corresponding output sequences.
@@ -248,10 +1721,15 @@ fn child_process(slave: OwnedFd, command: &str, args: &[String]) -> Result<()> {
 /// # Arguments
 /// * `input` - Input byte sequence from user
 /// * `keymap` - Key mapping configuration
+/// * `on_match` - Called with the input sequence of each match, in order
 ///
 /// # Returns
 /// Processed byte sequence with mappings applied
-fn process_input_hook(input: &[u8], keymap: &KeyMap) -> Vec<u8> {
+fn process_input_hook_with_matches(
+    input: &[u8],
+    keymap: &KeyMap,
+    mut on_match: impl FnMut(&[u8]),
+) -> Vec<u8> {
     let mut result = Vec::new();
     let mut i = 0;
 
@@ -263,6 +1741,7 @@ fn process_input_hook(input: &[u8], keymap: &KeyMap) -> Vec<u8> {
             if input[i..].starts_with(hook_key) {
                 // If hook is found, add corresponding output
                 result.extend_from_slice(mapped);
+                on_match(hook_key);
                 i += hook_key.len();
                 matched = true;
                 break;
@@ -334,32 +1813,322 @@ fn get_terminal_size() -> Result<Winsize, nix::Error> {
     Ok(winsize)
 }
 
+/// Forwards terminal resizes to the PTY from a background thread until stopped.
+struct ResizeForwarder {
+    handle: signal_hook::iterator::Handle,
+    thread: thread::JoinHandle<nix::Result<()>>,
+}
+
+impl ResizeForwarder {
+    /// Stops the thread and waits for it to finish.
+    ///
+    /// # Returns
+    /// The last error of forwarding a window size, if any
+    fn stop(self) -> nix::Result<()> {
+        self.handle.close();
+        self.thread.join().unwrap_or(Ok(()))
+    }
+}
+
 /// Sets up signal handling for window resize events.
 ///
 /// Spawns a background thread to handle SIGWINCH signals and
-/// forward window size changes to the PTY.
+/// forward window size changes to the PTY. The thread holds its own
+/// reference to the master, so the fd stays valid while it runs.
 ///
 /// # Arguments
 /// * `master` - PTY master file descriptor for ioctl calls
-fn setup_signal_handler(master: &OwnedFd) -> Result<()> {
-    let master_fd = master.as_raw_fd(); // Get raw fd for use in signal handler
-
-    thread::spawn(move || {
-        let mut signals = match Signals::new([SIGWINCH]) {
-            Ok(s) => s,
-            Err(_) => return,
-        };
+fn setup_signal_handler(master: &Arc<OwnedFd>) -> Result<ResizeForwarder> {
+    let master = Arc::clone(master);
+    let mut signals = Signals::new([SIGWINCH])?;
+    let handle = signals.handle();
 
+    let thread = thread::spawn(move || {
+        let mut result = Ok(());
         for signal in signals.forever() {
             if signal == SIGWINCH {
-                if let Ok(winsize) = get_terminal_size() {
-                    unsafe {
-                        nix::libc::ioctl(master_fd, nix::libc::TIOCSWINSZ, &winsize);
-                    }
+                if let Err(e) = forward_terminal_size(master.as_raw_fd()) {
+                    result = Err(e);
                 }
             }
         }
+        result
     });
 
-    Ok(())
+    Ok(ResizeForwarder { handle, thread })
+}
+
+/// Copies the current terminal window size to the PTY.
+///
+/// # Arguments
+/// * `master_fd` - PTY master file descriptor for the ioctl call
+fn forward_terminal_size(master_fd: RawFd) -> Result<(), nix::Error> {
+    let winsize = get_terminal_size()?;
+    let res = unsafe { nix::libc::ioctl(master_fd, nix::libc::TIOCSWINSZ, &winsize) };
+    nix::Error::result(res).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::Command;
+    use test_harness::MockPty;
+
+    /// Every backend available on this platform.
+    fn backends() -> Vec<Backend> {
+        vec![
+            Backend::Select,
+            #[cfg(target_os = "linux")]
+            Backend::Epoll,
+            #[cfg(target_os = "linux")]
+            Backend::IoUring,
+            #[cfg(kqueue)]
+            Backend::Kqueue,
+        ]
+    }
+
+    #[test]
+    fn input_is_remapped_before_reaching_child() {
+        let keymap = KeyMap::from([(b"a".to_vec(), b"x".to_vec()), (vec![0x03], vec![])]);
+
+        for backend in backends() {
+            let mut pty = MockPty::with_idle_child();
+            let keyboard = pty.keyboard();
+            keyboard.send(b"abc".to_vec()).unwrap();
+            keyboard.send(vec![0x03, b'a']).unwrap();
+            pty.close_stdin();
+
+            let config = ParentConfig {
+                keymap: keymap.clone(),
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_child, b"xbcx", "{backend:?}");
+            assert!(!outcome.child_exited, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn child_output_is_forwarded_unchanged() {
+        let keymap = KeyMap::from([(b"a".to_vec(), b"x".to_vec())]);
+
+        for backend in backends() {
+            let mut pty = MockPty::with_idle_child();
+            pty.child_writes(b"abc\r\n");
+            pty.close_slave();
+
+            let config = ParentConfig {
+                keymap: keymap.clone(),
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_stdout, b"abc\r\n", "{backend:?}");
+            assert!(outcome.to_child.is_empty(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn small_buffers_forward_everything() {
+        let written: Vec<u8> = (0..5000u32).map(|i| b'a' + (i % 26) as u8).collect();
+
+        for backend in backends() {
+            let config = ParentConfig {
+                backend,
+                input_buffer_size: 16,
+                pty_buffer_size: 64,
+                ..ParentConfig::default()
+            };
+
+            let mut pty = MockPty::with_idle_child();
+            pty.child_writes(&written);
+            pty.close_slave();
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_stdout, written, "{backend:?}");
+
+            let mut pty = MockPty::with_idle_child();
+            pty.keyboard().send(written[..100].to_vec()).unwrap();
+            pty.close_stdin();
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_child, written[..100], "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn on_match_exec_keys_pass_through_unless_remapped() {
+        let keymap = KeyMap::from([(b"b".to_vec(), b"y".to_vec())]);
+
+        let mut pty = MockPty::with_idle_child();
+        pty.keyboard().send(b"abc".to_vec()).unwrap();
+        pty.close_stdin();
+
+        let config = ParentConfig {
+            keymap,
+            on_match_exec: vec![
+                (b"a".to_vec(), "true".to_string()),
+                (b"b".to_vec(), "true".to_string()),
+            ],
+            ..ParentConfig::default()
+        };
+        let outcome = pty.run(&config).unwrap();
+        assert_eq!(outcome.to_child, b"ayc");
+    }
+
+    #[test]
+    fn keymap_scope_applies_until_the_next_one() {
+        let matches = Args::command().get_matches_from([
+            "cli-keyhook",
+            "-k",
+            "61:62",
+            "--keymap-scope",
+            "both",
+            "-k",
+            "0d0a:0a",
+            "--keymap-scope",
+            "output-only",
+            "-k",
+            "63:64",
+            "cat",
+        ]);
+        let args = Args::from_arg_matches(&matches).unwrap();
+
+        let (input, output) = split_keymaps_by_scope(&matches, args.keymaps);
+        assert_eq!(
+            input,
+            KeyMap::from([
+                (b"a".to_vec(), b"b".to_vec()),
+                (b"\r\n".to_vec(), b"\n".to_vec())
+            ])
+        );
+        assert_eq!(
+            output,
+            KeyMap::from([
+                (b"\r\n".to_vec(), b"\n".to_vec()),
+                (b"c".to_vec(), b"d".to_vec())
+            ])
+        );
+    }
+
+    #[test]
+    fn passed_fd_is_read_as_input_until_its_end() {
+        let keymap = KeyMap::from([(b"a".to_vec(), b"x".to_vec())]);
+
+        for backend in backends() {
+            let mut pty = MockPty::new(Command::new("sleep").arg("0.3"));
+            let mut sender = pty.pass_fd();
+            sender.write_all(b"abc").unwrap();
+            drop(sender);
+
+            let config = ParentConfig {
+                keymap: keymap.clone(),
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_child, b"xbc", "{backend:?}");
+            // The end of the passed fd does not end the session
+            assert!(outcome.child_exited, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn passed_regular_file_is_read_through_once() {
+        let keymap = KeyMap::from([(b"a".to_vec(), b"x".to_vec())]);
+        let path = std::env::temp_dir().join(format!("cli-keyhook-passed-{}", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+
+        for backend in backends() {
+            let mut pty = MockPty::new(Command::new("sleep").arg("0.3"));
+            pty.pass_file(std::fs::File::open(&path).unwrap());
+
+            let config = ParentConfig {
+                keymap: keymap.clone(),
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_child, b"xbc", "{backend:?}");
+            assert!(outcome.child_exited, "{backend:?}");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn injected_input_goes_through_the_input_chain() {
+        let keymap = KeyMap::from([(b"b".to_vec(), b"x".to_vec())]);
+
+        for backend in backends() {
+            let mut pty = MockPty::new(Command::new("sleep").arg("0.3"));
+            let mut injector = pty.injector();
+            injector.write_all(b"ab").unwrap();
+            drop(injector);
+
+            let config = ParentConfig {
+                keymap: keymap.clone(),
+                input_char_map: HashMap::from([('a', 'b')]),
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert_eq!(outcome.to_child, b"xx", "{backend:?}");
+            assert!(outcome.child_exited, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn loop_ends_when_child_exits() {
+        for backend in backends() {
+            let pty = MockPty::new(&mut Command::new("true"));
+
+            let config = ParentConfig {
+                backend,
+                ..ParentConfig::default()
+            };
+            let outcome = pty.run(&config).unwrap();
+            assert!(outcome.child_exited, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn resize_after_child_exit_keeps_master_valid() {
+        let winsize = |fd: &OwnedFd| {
+            let mut winsize = Winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            unsafe { nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCGWINSZ, &mut winsize) };
+            (winsize.ws_row, winsize.ws_col)
+        };
+        let tiny = Winsize {
+            ws_row: 1,
+            ws_col: 1,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = nix::pty::openpty(&tiny, None).unwrap();
+        let master = Arc::new(pty.master);
+        let observer = master.try_clone().unwrap();
+        let forwarder = setup_signal_handler(&master).unwrap();
+
+        // The child has exited and the parent has closed its side of the PTY
+        drop(pty.slave);
+        drop(master);
+
+        let expected = get_terminal_size().unwrap();
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGWINCH).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while winsize(&observer) != (expected.ws_row, expected.ws_col) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "SIGWINCH not forwarded"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(forwarder.stop(), Ok(()));
+    }
 }
@@ -0,0 +1,63 @@
+//! Periodic jemalloc heap profile dumps for tracking memory growth over long sessions.
+
+use anyhow::{anyhow, Context, Result};
+use std::ffi::{c_char, CString};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Enables the profiler at allocator initialization but leaves sampling inactive,
+/// so sessions without `--memory-profile` pay no sampling overhead.
+#[export_name = "_rjem_malloc_conf"]
+static MALLOC_CONF: &[u8; 28] = b"prof:true,prof_active:false\0";
+
+/// Writes numbered heap profiles to `<path>.<seq>.jemalloc.prof`.
+pub struct MemoryProfiler {
+    path: PathBuf,
+    seq: AtomicU64,
+}
+
+impl MemoryProfiler {
+    /// Activates heap sampling and spawns a background thread dumping a profile every `interval`.
+    ///
+    /// # Arguments
+    /// * `path` - Path prefix for the dumped profiles
+    /// * `interval` - Time between two consecutive dumps
+    pub fn start(path: &Path, interval: Duration) -> Result<Arc<Self>> {
+        // SAFETY: `prof.active` is a documented boolean mallctl.
+        unsafe { tikv_jemalloc_ctl::raw::write(b"prof.active\0", true) }
+            .map_err(|e| anyhow!("failed to activate heap profiling ({e})"))?;
+
+        let profiler = Arc::new(Self {
+            path: path.to_path_buf(),
+            seq: AtomicU64::new(0),
+        });
+
+        let dumper = Arc::clone(&profiler);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if let Err(e) = dumper.dump() {
+                eprintln!("cli-keyhook: {e:#}");
+            }
+        });
+
+        Ok(profiler)
+    }
+
+    /// Dumps the current heap profile to the next file in the sequence.
+    pub fn dump(&self) -> Result<()> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let file = format!("{}.{seq}.jemalloc.prof", self.path.display());
+        let file_c = CString::new(file.as_str())?;
+
+        // SAFETY: `prof.dump` takes a NUL-terminated path that outlives the call.
+        unsafe { tikv_jemalloc_ctl::raw::write(b"prof.dump\0", file_c.as_ptr() as *const c_char) }
+            .map_err(|e| anyhow!("{e}"))
+            .with_context(|| format!("failed to dump heap profile to '{file}'"))
+    }
+}
@@ -0,0 +1,132 @@
+//! `--input-from-midi`: typing keystrokes from the notes of a MIDI controller.
+//!
+//! Events are read from the ALSA sequencer port by `aseqdump` from alsa-utils,
+//! which prints one line per event.
+
+use crate::{hex_decode, inject, spawn_tool};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::os::fd::OwnedFd;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// Parses a MIDI keymap in the format "note:hex".
+///
+/// # Arguments
+/// * `s` - A MIDI note number from 0 to 127 and the hex encoded keystrokes it types
+///
+/// # Returns
+/// * `Ok((note, keystrokes))` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_midi_keymap(s: &str) -> Result<(u8, Vec<u8>), String> {
+    let (note, keystrokes) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid MIDI keymap format '{s}', expected format 'note:hex'"))?;
+    let note = note
+        .parse()
+        .ok()
+        .filter(|note| *note < 128)
+        .ok_or_else(|| format!("invalid MIDI note '{note}', expected a number from 0 to 127"))?;
+
+    Ok((note, hex_decode(keystrokes)?))
+}
+
+/// A running `aseqdump`, which is killed when dropped.
+pub struct MidiListener {
+    aseqdump: Child,
+}
+
+impl MidiListener {
+    /// Starts `aseqdump` and spawns a thread typing the keystrokes of played notes.
+    ///
+    /// # Arguments
+    /// * `port` - ALSA sequencer port, like `20:0` or a client name
+    /// * `notes` - Keystrokes typed for each note
+    /// * `injector` - Write end of the pipe read by the event loop, owned by the thread
+    pub fn spawn(port: &str, notes: HashMap<u8, Vec<u8>>, injector: OwnedFd) -> Result<Self> {
+        let mut aseqdump = spawn_tool(
+            Command::new("aseqdump")
+                .args(["--port", port])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+            "alsa-utils",
+        )
+        .with_context(|| format!("cannot read MIDI port '{port}'"))?;
+        let stdout = aseqdump.stdout.take().expect("stdout is piped");
+
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                let Some(keystrokes) = parse_note_on(&line).and_then(|note| notes.get(&note))
+                else {
+                    continue;
+                };
+
+                if inject(&injector, keystrokes).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { aseqdump })
+    }
+}
+
+impl Drop for MidiListener {
+    fn drop(&mut self) {
+        let _ = self.aseqdump.kill();
+        let _ = self.aseqdump.wait();
+    }
+}
+
+/// Returns the note of a note-on event printed by `aseqdump`.
+///
+/// Lines look like ` 20:0   Note on                 0, note 60, velocity 100`.
+/// A note-on with velocity 0 releases the note, so it is not returned either.
+fn parse_note_on(line: &str) -> Option<u8> {
+    let (_, data) = line.split_once("Note on")?;
+    let field = |name: &str| -> Option<u8> {
+        data.split(',')
+            .find_map(|field| field.trim().strip_prefix(name))?
+            .trim()
+            .parse()
+            .ok()
+    };
+
+    let note = field("note ")?;
+    (field("velocity ")? > 0).then_some(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_on_events_are_recognized() {
+        assert_eq!(
+            parse_note_on(" 20:0   Note on                 0, note 60, velocity 100"),
+            Some(60)
+        );
+        assert_eq!(
+            parse_note_on(" 20:0   Note on                 0, note 60, velocity 0"),
+            None
+        );
+        assert_eq!(
+            parse_note_on(" 20:0   Note off                0, note 60, velocity 64"),
+            None
+        );
+        assert_eq!(
+            parse_note_on("Source  Event                  Ch  Data"),
+            None
+        );
+    }
+
+    #[test]
+    fn midi_keymap_is_validated() {
+        assert_eq!(parse_midi_keymap("60:6c730d"), Ok((60, b"ls\r".to_vec())));
+        assert!(parse_midi_keymap("128:0d").is_err());
+        assert!(parse_midi_keymap("60").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Event loop backends driving the parent process.
+//!
+//! A [`Poller`] waits until one of the registered sources has data and then hands
+//! that data out through [`Poller::read`]. Readiness-based backends read from the fd
+//! on demand, while completion-based backends return what the kernel already read.
+
+#[cfg(target_os = "linux")]
+mod epoll;
+#[cfg(kqueue)]
+mod kqueue;
+mod select;
+#[cfg(target_os = "linux")]
+mod uring;
+
+use anyhow::Result;
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+/// An input of the parent process loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The user's terminal.
+    Stdin,
+    /// The PTY master, carrying the child's output.
+    Master,
+    /// An fd received with `--fd-passing`, carrying additional input.
+    Passed,
+    /// A serial device opened with `--input-from-serial`, carrying additional input.
+    Serial,
+    /// A pipe carrying the input of `--control-socket`, `--input-from-qr`,
+    /// `--input-from-midi` and `--input-from-clipboard`.
+    Injected,
+}
+
+/// Something the parent process loop has to react to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The source has data (or EOF) to read.
+    Readable(Source),
+    /// The terminal was resized; only reported by backends handling `SIGWINCH` themselves.
+    #[cfg_attr(not(kqueue), allow(dead_code))]
+    Resized,
+}
+
+/// The system facility used to wait for input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Portable `select(2)`.
+    Select,
+    /// Linux `epoll(7)`, edge-triggered on the PTY master.
+    #[cfg(target_os = "linux")]
+    Epoll,
+    /// Linux `io_uring(7)` with one outstanding `read` per source.
+    #[cfg(target_os = "linux")]
+    IoUring,
+    /// BSD `kqueue(2)`, also receiving `SIGWINCH` as an event.
+    #[cfg(kqueue)]
+    Kqueue,
+}
+
+/// `kqueue` where it is available, `select` elsewhere.
+#[cfg(kqueue)]
+const DEFAULT_BACKEND: Backend = Backend::Kqueue;
+#[cfg(not(kqueue))]
+const DEFAULT_BACKEND: Backend = Backend::Select;
+
+impl Default for Backend {
+    fn default() -> Self {
+        DEFAULT_BACKEND
+    }
+}
+
+impl Backend {
+    /// Whether the poller reports [`Event::Resized`], replacing the `SIGWINCH` thread.
+    pub fn handles_resize(self) -> bool {
+        #[cfg(kqueue)]
+        if self == Backend::Kqueue {
+            return true;
+        }
+        false
+    }
+
+    /// Creates a poller watching `sources`, whose reads use buffers of `buffer_size(source)` bytes.
+    ///
+    /// # Arguments
+    /// * `sources` - Sources to wait on and their fds
+    /// * `buffer_size` - Maximum number of bytes returned by a single read of a source
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    pub fn create<'fd>(
+        self,
+        sources: &[(Source, BorrowedFd<'fd>)],
+        buffer_size: impl Fn(Source) -> usize,
+    ) -> Result<Box<dyn Poller + 'fd>> {
+        Ok(match self {
+            Backend::Select => Box::new(select::SelectPoller::new(sources)),
+            #[cfg(target_os = "linux")]
+            Backend::Epoll => Box::new(epoll::EpollPoller::new(sources)?),
+            #[cfg(target_os = "linux")]
+            Backend::IoUring => Box::new(uring::UringPoller::new(sources, buffer_size)?),
+            #[cfg(kqueue)]
+            Backend::Kqueue => Box::new(kqueue::KqueuePoller::new(sources)?),
+        })
+    }
+}
+
+/// Waits for and reads data from the parent process's sources.
+pub trait Poller {
+    /// Waits up to `timeout` and returns what happened in the meantime.
+    fn wait(&mut self, timeout: Duration) -> nix::Result<Vec<Event>>;
+
+    /// Reads data from a source reported by the last `wait`.
+    ///
+    /// Returns `Ok(0)` at end of file, like `read(2)`.
+    fn read(&mut self, source: Source, buf: &mut [u8]) -> nix::Result<usize>;
+
+    /// Stops watching a source, e.g. after it reached end of file.
+    fn remove(&mut self, source: Source);
+}
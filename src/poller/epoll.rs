@@ -0,0 +1,118 @@
+//! The Linux `epoll(7)` backend.
+//!
+//! The PTY master is registered edge-triggered, so a burst of child output wakes the
+//! loop once instead of on every `epoll_wait`. Since the loop reads at most one buffer
+//! per wakeup, a read that returns data marks the master as still pending and it is
+//! checked again with a zero-timeout `poll` instead of waiting for a new edge: more
+//! data, or the end of the output, may be left without one.
+//!
+//! Regular files cannot be added to an epoll instance. Like `select` and `poll` would,
+//! the poller reports them as always readable instead.
+
+use super::{Event, Poller, Source};
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout};
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+/// Waits on all sources with a single epoll instance.
+pub struct EpollPoller<'fd> {
+    epoll: Epoll,
+    sources: Vec<(Source, BorrowedFd<'fd>)>,
+    /// Whether the last master read returned data, so more data or the end may be left.
+    master_pending: bool,
+    /// Sources epoll cannot watch, reported as readable on every `wait`.
+    always_ready: Vec<Source>,
+}
+
+impl<'fd> EpollPoller<'fd> {
+    pub fn new(sources: &[(Source, BorrowedFd<'fd>)]) -> Result<Self> {
+        let epoll =
+            Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC).context("failed to create epoll")?;
+
+        let mut always_ready = Vec::new();
+        for (index, &(source, fd)) in sources.iter().enumerate() {
+            let flags = match source {
+                Source::Master => EpollFlags::EPOLLIN | EpollFlags::EPOLLET,
+                _ => EpollFlags::EPOLLIN,
+            };
+            match epoll.add(fd, EpollEvent::new(flags, index as u64)) {
+                Err(Errno::EPERM) if source != Source::Master => always_ready.push(source),
+                result => {
+                    result.with_context(|| format!("failed to register {source:?} with epoll"))?
+                }
+            }
+        }
+
+        Ok(Self {
+            epoll,
+            sources: sources.to_vec(),
+            master_pending: false,
+            always_ready,
+        })
+    }
+
+    fn fd(&self, source: Source) -> BorrowedFd<'fd> {
+        self.sources
+            .iter()
+            .find(|(s, _)| *s == source)
+            .map(|(_, fd)| *fd)
+            .expect("source is registered")
+    }
+
+    /// Checks whether the master is still readable after a read that returned data.
+    fn master_still_readable(&self) -> bool {
+        let mut fds = [PollFd::new(self.fd(Source::Master), PollFlags::POLLIN)];
+        matches!(nix::poll::poll(&mut fds, PollTimeout::ZERO), Ok(n) if n > 0)
+    }
+}
+
+impl Poller for EpollPoller<'_> {
+    fn wait(&mut self, timeout: Duration) -> nix::Result<Vec<Event>> {
+        let mut ready: Vec<Event> = self
+            .always_ready
+            .iter()
+            .map(|&source| Event::Readable(source))
+            .collect();
+        if self.master_pending {
+            self.master_pending = false;
+            if self.master_still_readable() {
+                ready.push(Event::Readable(Source::Master));
+            }
+        }
+
+        let timeout = if ready.is_empty() {
+            EpollTimeout::try_from(timeout).unwrap_or(EpollTimeout::MAX)
+        } else {
+            EpollTimeout::ZERO
+        };
+
+        let mut events = [EpollEvent::empty(); 4];
+        let n = self.epoll.wait(&mut events, timeout)?;
+
+        for event in &events[..n] {
+            let event = Event::Readable(self.sources[event.data() as usize].0);
+            if !ready.contains(&event) {
+                ready.push(event);
+            }
+        }
+
+        Ok(ready)
+    }
+
+    fn read(&mut self, source: Source, buf: &mut [u8]) -> nix::Result<usize> {
+        let n = nix::unistd::read(self.fd(source), buf)?;
+        if source == Source::Master && n > 0 {
+            self.master_pending = true;
+        }
+        Ok(n)
+    }
+
+    fn remove(&mut self, source: Source) {
+        self.always_ready.retain(|&s| s != source);
+        // Keep the entry, since event data refers to sources by index
+        let _ = self.epoll.delete(self.fd(source));
+    }
+}
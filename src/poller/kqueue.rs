@@ -0,0 +1,116 @@
+//! The BSD `kqueue(2)` backend, the default on macOS and the BSDs.
+//!
+//! Besides `EVFILT_READ` for every source, `SIGWINCH` is registered as an
+//! `EVFILT_SIGNAL` event, so resizes are handled by the loop itself and no
+//! signal handling thread is needed.
+
+use super::{Event, Poller, Source};
+use anyhow::{Context, Result};
+use nix::libc::{intptr_t, timespec, uintptr_t};
+use nix::sys::event::{EvFlags, EventFilter, FilterFlag, KEvent, Kqueue};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::time::Duration;
+
+/// `udata` of the `SIGWINCH` event; read events use the index of their source.
+const RESIZE_UDATA: intptr_t = -1;
+
+/// Waits on all sources and `SIGWINCH` with a single kqueue.
+pub struct KqueuePoller<'fd> {
+    kqueue: Kqueue,
+    sources: Vec<(Source, BorrowedFd<'fd>)>,
+}
+
+impl<'fd> KqueuePoller<'fd> {
+    pub fn new(sources: &[(Source, BorrowedFd<'fd>)]) -> Result<Self> {
+        let kqueue = Kqueue::new().context("failed to create kqueue")?;
+
+        let mut changes: Vec<KEvent> = sources
+            .iter()
+            .enumerate()
+            .map(|(index, (_, fd))| {
+                KEvent::new(
+                    fd.as_raw_fd() as uintptr_t,
+                    EventFilter::EVFILT_READ,
+                    EvFlags::EV_ADD,
+                    FilterFlag::empty(),
+                    0,
+                    index as intptr_t,
+                )
+            })
+            .collect();
+        // Signal events are recorded even though SIGWINCH is ignored by default
+        changes.push(KEvent::new(
+            nix::libc::SIGWINCH as uintptr_t,
+            EventFilter::EVFILT_SIGNAL,
+            EvFlags::EV_ADD,
+            FilterFlag::empty(),
+            0,
+            RESIZE_UDATA,
+        ));
+        kqueue
+            .kevent(&changes, &mut [], None)
+            .context("failed to register events with kqueue")?;
+
+        Ok(Self {
+            kqueue,
+            sources: sources.to_vec(),
+        })
+    }
+
+    fn fd(&self, source: Source) -> BorrowedFd<'fd> {
+        self.sources
+            .iter()
+            .find(|(s, _)| *s == source)
+            .map(|(_, fd)| *fd)
+            .expect("source is registered")
+    }
+}
+
+impl Poller for KqueuePoller<'_> {
+    fn wait(&mut self, timeout: Duration) -> nix::Result<Vec<Event>> {
+        let timeout = timespec {
+            tv_sec: timeout.as_secs() as _,
+            tv_nsec: timeout.subsec_nanos() as _,
+        };
+        let empty = KEvent::new(
+            0,
+            EventFilter::EVFILT_READ,
+            EvFlags::empty(),
+            FilterFlag::empty(),
+            0,
+            0,
+        );
+        let mut events = [empty; 4];
+        let n = self.kqueue.kevent(&[], &mut events, Some(timeout))?;
+
+        let mut ready = Vec::new();
+        for event in &events[..n] {
+            let event = match event.udata() {
+                RESIZE_UDATA => Event::Resized,
+                index => Event::Readable(self.sources[index as usize].0),
+            };
+            if !ready.contains(&event) {
+                ready.push(event);
+            }
+        }
+
+        Ok(ready)
+    }
+
+    fn read(&mut self, source: Source, buf: &mut [u8]) -> nix::Result<usize> {
+        nix::unistd::read(self.fd(source), buf)
+    }
+
+    fn remove(&mut self, source: Source) {
+        // Keep the entry, since event data refers to sources by index
+        let change = KEvent::new(
+            self.fd(source).as_raw_fd() as uintptr_t,
+            EventFilter::EVFILT_READ,
+            EvFlags::EV_DELETE,
+            FilterFlag::empty(),
+            0,
+            0,
+        );
+        let _ = self.kqueue.kevent(&[change], &mut [], None);
+    }
+}
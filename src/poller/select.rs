@@ -0,0 +1,72 @@
+//! The default `select(2)` backend.
+
+use super::{Event, Poller, Source};
+use nix::sys::select::FdSet;
+use nix::sys::time::TimeVal;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::time::Duration;
+
+/// Waits on all sources with a single `select` call.
+pub struct SelectPoller<'fd> {
+    sources: Vec<(Source, BorrowedFd<'fd>)>,
+}
+
+impl<'fd> SelectPoller<'fd> {
+    pub fn new(sources: &[(Source, BorrowedFd<'fd>)]) -> Self {
+        Self {
+            sources: sources.to_vec(),
+        }
+    }
+
+    fn fd(&self, source: Source) -> BorrowedFd<'fd> {
+        self.sources
+            .iter()
+            .find(|(s, _)| *s == source)
+            .map(|(_, fd)| *fd)
+            .expect("source is registered")
+    }
+}
+
+impl Poller for SelectPoller<'_> {
+    fn wait(&mut self, timeout: Duration) -> nix::Result<Vec<Event>> {
+        let mut read_fds = FdSet::new();
+        for (_, fd) in &self.sources {
+            read_fds.insert(*fd);
+        }
+
+        let nfds = self
+            .sources
+            .iter()
+            .map(|(_, fd)| fd.as_raw_fd())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let mut timeout = TimeVal::new(timeout.as_secs() as _, timeout.subsec_micros() as _);
+
+        let n = nix::sys::select::select(
+            Some(nfds),
+            Some(&mut read_fds),
+            None,
+            None,
+            Some(&mut timeout),
+        )?;
+
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .sources
+            .iter()
+            .filter(|(_, fd)| read_fds.contains(*fd))
+            .map(|(source, _)| Event::Readable(*source))
+            .collect())
+    }
+
+    fn read(&mut self, source: Source, buf: &mut [u8]) -> nix::Result<usize> {
+        nix::unistd::read(self.fd(source), buf)
+    }
+
+    fn remove(&mut self, source: Source) {
+        self.sources.retain(|(s, _)| *s != source);
+    }
+}
@@ -0,0 +1,197 @@
+//! The Linux `io_uring(7)` backend.
+//!
+//! Every source always has one `read` submitted to the ring. `wait` reaps completed
+//! reads, and `read` hands their data out before submitting the next one, so no
+//! readiness round trip is needed between the kernel and the loop.
+
+use super::{Event, Poller, Source};
+use anyhow::{Context, Result};
+use io_uring::{opcode, types, IoUring};
+use nix::errno::Errno;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::time::Duration;
+
+/// `user_data` of the timeout entry; read entries use the index of their slot.
+const TIMEOUT_USER_DATA: u64 = u64::MAX;
+
+/// Submission queue size; a few entries per source are more than enough.
+const RING_ENTRIES: u32 = 16;
+
+/// A source together with the buffer its outstanding read writes into.
+struct Slot<'fd> {
+    source: Source,
+    fd: BorrowedFd<'fd>,
+    buffer: Vec<u8>,
+    /// Whether a read is submitted and not completed yet.
+    in_flight: bool,
+    /// Result of the completed read not handed out by `read` yet.
+    completed: Option<i32>,
+    /// Whether the source is still reported, see [`Poller::remove`].
+    watched: bool,
+}
+
+/// Keeps a `read` in flight on every source and reaps them from the completion queue.
+pub struct UringPoller<'fd> {
+    ring: IoUring,
+    slots: Vec<Slot<'fd>>,
+    /// Boxed so that its address stays stable while the kernel references it.
+    timespec: Box<types::Timespec>,
+    timeout_in_flight: bool,
+}
+
+impl<'fd> UringPoller<'fd> {
+    pub fn new(
+        sources: &[(Source, BorrowedFd<'fd>)],
+        buffer_size: impl Fn(Source) -> usize,
+    ) -> Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)
+            .context("failed to set up io_uring (requires Linux 5.1 or later)")?;
+
+        let mut poller = Self {
+            ring,
+            slots: sources
+                .iter()
+                .map(|&(source, fd)| Slot {
+                    source,
+                    fd,
+                    buffer: vec![0; buffer_size(source)],
+                    in_flight: false,
+                    completed: None,
+                    watched: true,
+                })
+                .collect(),
+            timespec: Box::default(),
+            timeout_in_flight: false,
+        };
+
+        for index in 0..poller.slots.len() {
+            poller.submit_read(index)?;
+        }
+
+        Ok(poller)
+    }
+
+    fn submit_read(&mut self, index: usize) -> nix::Result<()> {
+        let slot = &mut self.slots[index];
+        let entry = opcode::Read::new(
+            types::Fd(slot.fd.as_raw_fd()),
+            slot.buffer.as_mut_ptr(),
+            slot.buffer.len() as u32,
+        )
+        // Read at the current position, so that a regular file is read through once
+        .offset(u64::MAX)
+        .build()
+        .user_data(index as u64);
+
+        // SAFETY: the buffer is owned by the slot and outlives the read, see `Drop`.
+        unsafe { self.ring.submission().push(&entry) }.map_err(|_| Errno::EBUSY)?;
+        slot.in_flight = true;
+
+        Ok(())
+    }
+
+    fn reap(&mut self) {
+        for cqe in self.ring.completion() {
+            if cqe.user_data() == TIMEOUT_USER_DATA {
+                self.timeout_in_flight = false;
+            } else if let Some(slot) = self.slots.get_mut(cqe.user_data() as usize) {
+                slot.in_flight = false;
+                slot.completed = Some(cqe.result());
+            }
+        }
+    }
+}
+
+impl Poller for UringPoller<'_> {
+    fn wait(&mut self, timeout: Duration) -> nix::Result<Vec<Event>> {
+        if !self
+            .slots
+            .iter()
+            .any(|slot| slot.watched && slot.completed.is_some())
+        {
+            if !self.timeout_in_flight {
+                // The timeout also completes as soon as any read does
+                *self.timespec = timeout.into();
+                let entry = opcode::Timeout::new(&*self.timespec)
+                    .count(1)
+                    .build()
+                    .user_data(TIMEOUT_USER_DATA);
+                // SAFETY: the timespec is boxed and owned by the poller.
+                unsafe { self.ring.submission().push(&entry) }.map_err(|_| Errno::EBUSY)?;
+                self.timeout_in_flight = true;
+            }
+
+            self.ring.submit_and_wait(1).map_err(io_to_nix)?;
+        }
+        self.reap();
+
+        Ok(self
+            .slots
+            .iter()
+            .filter(|slot| slot.watched && slot.completed.is_some())
+            .map(|slot| Event::Readable(slot.source))
+            .collect())
+    }
+
+    fn read(&mut self, source: Source, buf: &mut [u8]) -> nix::Result<usize> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.source == source)
+            .expect("source is registered");
+        let Some(result) = self.slots[index].completed.take() else {
+            return Err(Errno::EAGAIN);
+        };
+
+        if result < 0 {
+            self.submit_read(index)?;
+            return Err(Errno::from_raw(-result));
+        }
+
+        let n = result as usize;
+        buf[..n].copy_from_slice(&self.slots[index].buffer[..n]);
+        if n > 0 {
+            self.submit_read(index)?;
+        }
+
+        Ok(n)
+    }
+
+    fn remove(&mut self, source: Source) {
+        let Some(index) = self.slots.iter().position(|slot| slot.source == source) else {
+            return;
+        };
+        self.slots[index].watched = false;
+        if self.slots[index].in_flight {
+            let entry = opcode::AsyncCancel::new(index as u64).build();
+            // SAFETY: cancellation entries reference no memory.
+            let _ = unsafe { self.ring.submission().push(&entry) };
+        }
+    }
+}
+
+impl Drop for UringPoller<'_> {
+    /// Cancels outstanding reads and waits for them, since the kernel may otherwise
+    /// still write into the slot buffers after they are freed.
+    fn drop(&mut self) {
+        for index in 0..self.slots.len() {
+            if self.slots[index].in_flight {
+                let entry = opcode::AsyncCancel::new(index as u64).build();
+                // SAFETY: cancellation entries reference no memory.
+                let _ = unsafe { self.ring.submission().push(&entry) };
+            }
+        }
+
+        while self.slots.iter().any(|slot| slot.in_flight) {
+            if self.ring.submit_and_wait(1).is_err() {
+                break;
+            }
+            self.reap();
+        }
+    }
+}
+
+fn io_to_nix(e: io::Error) -> Errno {
+    Errno::from_raw(e.raw_os_error().unwrap_or(nix::libc::EIO))
+}
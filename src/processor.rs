@@ -0,0 +1,214 @@
+//! Stages transforming the byte streams between the user's terminal and the PTY.
+//!
+//! Each direction has its own [`Chain`] of processors, applied in order to every
+//! chunk read from the corresponding source.
+
+use crate::{hex_encode, hook, process_input_hook_with_matches, KeyMap};
+use std::collections::HashMap;
+
+/// A stage of a processor chain.
+///
+/// Processors see the stream in arbitrarily split chunks, so stages working on
+/// multi-byte units have to carry incomplete ones over to the next call.
+pub trait Processor: Send {
+    /// Transforms the next chunk of the stream.
+    fn process(&mut self, input: &[u8]) -> Vec<u8>;
+}
+
+/// Processors applied in order to one direction of the stream.
+#[derive(Default)]
+pub struct Chain {
+    processors: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+    /// Appends a processor to the end of the chain.
+    pub fn push(&mut self, processor: Box<dyn Processor>) {
+        self.processors.push(processor);
+    }
+
+    /// Passes a chunk through all processors; an empty chain returns the chunk unchanged.
+    pub fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut data = input.to_vec();
+        for processor in &mut self.processors {
+            data = processor.process(&data);
+        }
+        data
+    }
+}
+
+/// Replaces byte sequences according to a keymap.
+pub struct KeyProcessor {
+    keymap: KeyMap,
+    on_match: HashMap<Vec<u8>, String>,
+}
+
+impl KeyProcessor {
+    pub fn new(keymap: KeyMap) -> Self {
+        Self {
+            keymap,
+            on_match: HashMap::new(),
+        }
+    }
+
+    /// Runs `command` in the background whenever `key` is matched.
+    ///
+    /// A key missing from the keymap is matched and passed through unchanged.
+    pub fn on_match_exec(mut self, key: Vec<u8>, command: String) -> Self {
+        self.keymap
+            .entry(key.clone())
+            .or_insert_with(|| key.clone());
+        self.on_match.insert(key, command);
+        self
+    }
+}
+
+impl Processor for KeyProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        process_input_hook_with_matches(input, &self.keymap, |key| {
+            if let Some(command) = self.on_match.get(key) {
+                hook::spawn_shell(command, &[("CLI_KEYHOOK_MATCH", hex_encode(key))]);
+            }
+        })
+    }
+}
+
+/// When a [`CountedRemap`] applies, relative to the number of occurrences seen so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Occurrence {
+    /// Only on every Nth occurrence (`--remap-on-nth`).
+    EveryNth,
+    /// On the Nth occurrence and all following ones (`--remap-after-nth`).
+    FromNth,
+}
+
+/// A remap applied depending on how often its key has occurred.
+#[derive(Clone, Debug)]
+pub struct CountedRemap {
+    pub key: Vec<u8>,
+    pub n: u64,
+    pub output: Vec<u8>,
+    pub occurrence: Occurrence,
+}
+
+/// Counts occurrences of a key and remaps the ones selected by a [`CountedRemap`].
+///
+/// Everything else, including occurrences that are not remapped, goes through
+/// the wrapped processor, so counted remaps can be stacked around a [`KeyProcessor`].
+pub struct CountingProcessor {
+    inner: Box<dyn Processor>,
+    remap: CountedRemap,
+    count: u64,
+}
+
+impl CountingProcessor {
+    pub fn new(inner: Box<dyn Processor>, remap: CountedRemap) -> Self {
+        Self {
+            inner,
+            remap,
+            count: 0,
+        }
+    }
+}
+
+impl Processor for CountingProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let key = &self.remap.key;
+        let mut output = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+
+        while i < input.len() {
+            if !input[i..].starts_with(key) {
+                i += 1;
+                continue;
+            }
+
+            self.count += 1;
+            let applies = match self.remap.occurrence {
+                Occurrence::EveryNth => self.count % self.remap.n == 0,
+                Occurrence::FromNth => self.count >= self.remap.n,
+            };
+            if applies {
+                output.extend(self.inner.process(&input[start..i]));
+                output.extend_from_slice(&self.remap.output);
+                start = i + key.len();
+            }
+            i += key.len();
+        }
+        output.extend(self.inner.process(&input[start..]));
+
+        output
+    }
+}
+
+/// Reverses the order of the bytes in every group of `n` bytes.
+///
+/// Groups are counted from the start of the stream, and an incomplete group at the
+/// end of a chunk is held back until the rest of it arrives.
+pub struct ByteSwapProcessor {
+    n: usize,
+    incomplete: Vec<u8>,
+}
+
+impl ByteSwapProcessor {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            incomplete: Vec::new(),
+        }
+    }
+}
+
+impl Processor for ByteSwapProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.incomplete);
+        data.extend_from_slice(input);
+
+        let complete = data.len() - data.len() % self.n;
+        self.incomplete = data.split_off(complete);
+        for group in data.chunks_mut(self.n) {
+            group.reverse();
+        }
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counting(occurrence: Occurrence) -> CountingProcessor {
+        let keymap = KeyMap::from([(b"a".to_vec(), b"b".to_vec())]);
+        let remap = CountedRemap {
+            key: b"a".to_vec(),
+            n: 3,
+            output: b"X".to_vec(),
+            occurrence,
+        };
+        CountingProcessor::new(Box::new(KeyProcessor::new(keymap)), remap)
+    }
+
+    #[test]
+    fn remap_on_nth_counts_across_chunks() {
+        let mut processor = counting(Occurrence::EveryNth);
+        assert_eq!(processor.process(b"a-a"), b"b-b");
+        assert_eq!(processor.process(b"aaaa"), b"XbbX");
+    }
+
+    #[test]
+    fn remap_after_nth_applies_to_all_later_occurrences() {
+        let mut processor = counting(Occurrence::FromNth);
+        assert_eq!(processor.process(b"aaaaa"), b"bbXXX");
+    }
+
+    #[test]
+    fn byte_swap_groups_span_chunks() {
+        let mut processor = ByteSwapProcessor::new(2);
+        assert_eq!(processor.process(b"ABC"), b"BA");
+        assert_eq!(processor.process(b"DEF"), b"DCFE");
+        assert_eq!(processor.process(b"G"), b"");
+        assert_eq!(processor.process(b"H"), b"HG");
+    }
+}
@@ -0,0 +1,162 @@
+//! `--perf-record` and `--valgrind`: running the command under a profiler.
+//!
+//! The profiler is prepended to the command line, so it runs on the PTY in place
+//! of the command and profiles it as its own child. Its own messages go to files
+//! or stderr, never to the PTY, so they do not mix with the output of the command.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Returns the path of an executable found in `PATH`, if any.
+///
+/// # Arguments
+/// * `name` - Name of the executable
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| {
+            path.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+}
+
+/// Prepends `perf record` to a command line.
+///
+/// # Arguments
+/// * `output` - Path of the perf data file
+/// * `command` - Command to profile
+/// * `args` - Arguments of the command
+///
+/// # Returns
+/// The profiling command line, or `None` with a warning if `perf` cannot be found
+pub fn perf_record(output: &Path, command: &str, args: &[String]) -> Option<(String, Vec<String>)> {
+    if find_in_path("perf").is_none() {
+        eprintln!(
+            "cli-keyhook: warning: perf is not in PATH, running the command without --perf-record"
+        );
+        return None;
+    }
+
+    let mut perf_args = vec![
+        "record".to_string(),
+        "-o".to_string(),
+        output.display().to_string(),
+        "--".to_string(),
+        command.to_string(),
+    ];
+    perf_args.extend_from_slice(args);
+    Some(("perf".to_string(), perf_args))
+}
+
+/// Tool run by `--valgrind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ValgrindTool {
+    /// Memory errors and leaks, written as XML.
+    Memcheck,
+    /// Call graph profile, for `callgrind_annotate`.
+    Callgrind,
+    /// Cache and branch profile, for `cg_annotate`.
+    Cachegrind,
+    /// Data races between threads, written as XML.
+    Helgrind,
+}
+
+impl ValgrindTool {
+    /// Returns the options writing the results of the tool to `output`.
+    ///
+    /// Only the error reporting tools support XML; the profilers have their own format.
+    fn output_options(self, output: &Path) -> Vec<String> {
+        let output = output.display();
+        match self {
+            Self::Memcheck | Self::Helgrind => {
+                vec!["--xml=yes".to_string(), format!("--xml-file={output}")]
+            }
+            Self::Callgrind => vec![format!("--callgrind-out-file={output}")],
+            Self::Cachegrind => vec![format!("--cachegrind-out-file={output}")],
+        }
+    }
+}
+
+/// Prepends `valgrind --tool=TOOL` to a command line.
+///
+/// The messages valgrind would print to stderr go to `OUTPUT.log` instead, as the
+/// command's stderr is the PTY.
+///
+/// # Arguments
+/// * `tool` - Valgrind tool to run
+/// * `output` - Path of the results of the tool
+/// * `command` - Command to run
+/// * `args` - Arguments of the command
+///
+/// # Returns
+/// The valgrind command line, or `None` with a warning if `valgrind` cannot be found
+pub fn valgrind(
+    tool: ValgrindTool,
+    output: &Path,
+    command: &str,
+    args: &[String],
+) -> Option<(String, Vec<String>)> {
+    if find_in_path("valgrind").is_none() {
+        eprintln!(
+            "cli-keyhook: warning: valgrind is not in PATH, running the command without --valgrind"
+        );
+        return None;
+    }
+
+    let mut valgrind_args = vec![format!(
+        "--tool={}",
+        tool.to_possible_value()
+            .expect("no tool is skipped")
+            .get_name()
+    )];
+    valgrind_args.extend(tool.output_options(output));
+    valgrind_args.push(format!("--log-file={}.log", output.display()));
+    valgrind_args.push("--".to_string());
+    valgrind_args.push(command.to_string());
+    valgrind_args.extend_from_slice(args);
+    Some(("valgrind".to_string(), valgrind_args))
+}
+
+/// Prints the report of a perf data file to stderr.
+///
+/// # Arguments
+/// * `output` - Path of the perf data file written by [`perf_record`]
+pub fn perf_report(output: &Path) -> Result<()> {
+    let status = Command::new("perf")
+        .arg("report")
+        .arg("-i")
+        .arg(output)
+        .arg("--stdio")
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        .status()
+        .context("failed to run 'perf report'")?;
+    if !status.success() {
+        eprintln!("cli-keyhook: warning: 'perf report' failed ({status})");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_error_reporting_tools_write_xml() {
+        let output = Path::new("/tmp/out");
+        assert_eq!(
+            ValgrindTool::Helgrind.output_options(output),
+            ["--xml=yes", "--xml-file=/tmp/out"]
+        );
+        assert_eq!(
+            ValgrindTool::Callgrind.output_options(output),
+            ["--callgrind-out-file=/tmp/out"]
+        );
+    }
+}
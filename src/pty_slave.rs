@@ -0,0 +1,99 @@
+//! `--pty-slave-chmod` and `--pty-slave-chown`: opening up the PTY slave device to other users.
+//!
+//! `openpty` leaves the slave owned by the invoking user with mode 0620 or stricter, which
+//! some container setups cannot open from an unprivileged process.
+
+use anyhow::{Context, Result};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::fs::Permissions;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::PermissionsExt;
+
+/// Parses a file mode in octal, like "0666".
+///
+/// # Arguments
+/// * `s` - Octal digits, optionally with a leading 0
+///
+/// # Returns
+/// * `Ok(mode)` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid mode '{s}', expected octal digits like 0666"))
+}
+
+/// Parses an owner in the format "user:group", each given as a name or a numeric ID.
+///
+/// # Arguments
+/// * `s` - User and group separated by ':', like "1000:1000" or "nobody:nogroup"
+///
+/// # Returns
+/// * `Ok((uid, gid))` on success
+/// * `Err(error_message)` if the format is invalid or a name is unknown
+pub fn parse_owner(s: &str) -> Result<(Uid, Gid), String> {
+    let (user, group) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid owner format '{s}', expected format 'user:group'"))?;
+
+    let uid = match user.parse() {
+        Ok(uid) => Uid::from_raw(uid),
+        Err(_) => {
+            User::from_name(user)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("unknown user '{user}'"))?
+                .uid
+        }
+    };
+    let gid = match group.parse() {
+        Ok(gid) => Gid::from_raw(gid),
+        Err(_) => {
+            Group::from_name(group)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("unknown group '{group}'"))?
+                .gid
+        }
+    };
+
+    Ok((uid, gid))
+}
+
+/// Changes the mode of the device file of the PTY slave.
+///
+/// # Arguments
+/// * `slave` - PTY slave file descriptor
+/// * `mode` - New mode, including the permission bits
+pub fn chmod(slave: &OwnedFd, mode: u32) -> Result<()> {
+    let path = nix::unistd::ttyname(slave).context("failed to find the PTY slave device")?;
+    std::fs::set_permissions(&path, Permissions::from_mode(mode))
+        .with_context(|| format!("failed to change the mode of '{}'", path.display()))
+}
+
+/// Changes the owner of the device file of the PTY slave.
+///
+/// # Arguments
+/// * `slave` - PTY slave file descriptor
+/// * `(uid, gid)` - New owning user and group
+pub fn chown(slave: &OwnedFd, (uid, gid): (Uid, Gid)) -> Result<()> {
+    let path = nix::unistd::ttyname(slave).context("failed to find the PTY slave device")?;
+    nix::unistd::chown(&path, Some(uid), Some(gid))
+        .with_context(|| format!("failed to change the owner of '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_and_owner_are_parsed() {
+        assert_eq!(parse_mode("0666"), Ok(0o666));
+        assert!(parse_mode("0888").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert_eq!(
+            parse_owner("1000:100"),
+            Ok((Uid::from_raw(1000), Gid::from_raw(100)))
+        );
+        assert!(parse_owner("1000").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! `--input-from-qr` and `--output-to-qr`: moving text through QR codes.
+//!
+//! For input, frames are grabbed from the V4L2 device and decoded by `zbarcam` from
+//! the zbar tools, which prints one line per decoded code. For output, PNG images are
+//! written by `qrencode`.
+
+use crate::inject;
+use crate::processor::Processor;
+use crate::spawn_tool;
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Number of recently decoded codes that are not injected again.
+const REMEMBERED_CODES: usize = 16;
+
+/// Most bytes a QR code holds: version 40 in byte mode with the lowest error correction.
+const QR_CAPACITY: usize = 2953;
+
+/// Room kept in each code for the `i/n` header of a segment split across codes.
+const SEQUENCE_HEADER_CAPACITY: usize = 16;
+
+/// A running `zbarcam`, which is killed when dropped.
+pub struct QrScanner {
+    zbarcam: Child,
+}
+
+impl QrScanner {
+    /// Starts `zbarcam` and spawns a thread injecting decoded codes as input.
+    ///
+    /// # Arguments
+    /// * `device` - Path of the V4L2 camera device, like `/dev/video0`
+    /// * `injector` - Write end of the pipe read by the event loop, owned by the thread
+    pub fn spawn(device: &Path, injector: OwnedFd) -> Result<Self> {
+        let mut zbarcam = spawn_tool(
+            Command::new("zbarcam")
+                .args(["--nodisplay", "--raw", "-Sdisable", "-Sqrcode.enable"])
+                .arg(device)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+            "the zbar tools (zbar-tools on Debian and Ubuntu)",
+        )
+        .with_context(|| format!("cannot read QR codes from '{}'", device.display()))?;
+        let stdout = zbarcam.stdout.take().expect("stdout is piped");
+
+        thread::spawn(move || {
+            let mut recent = VecDeque::with_capacity(REMEMBERED_CODES);
+            for line in BufReader::new(stdout).split(b'\n') {
+                let Ok(code) = line else { break };
+                // A code stays in view for many frames, but should be typed once
+                if !remember(&mut recent, &code) {
+                    continue;
+                }
+
+                if inject(&injector, &code).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { zbarcam })
+    }
+}
+
+impl Drop for QrScanner {
+    fn drop(&mut self) {
+        let _ = self.zbarcam.kill();
+        let _ = self.zbarcam.wait();
+    }
+}
+
+/// Records the hash of `code` among the recent ones.
+///
+/// # Returns
+/// Whether `code` is new, i.e. not among the last [`REMEMBERED_CODES`] codes
+fn remember(recent: &mut VecDeque<u64>, code: &[u8]) -> bool {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    if recent.contains(&hash) {
+        return false;
+    }
+    if recent.len() == REMEMBERED_CODES {
+        recent.pop_front();
+    }
+    recent.push_back(hash);
+    true
+}
+
+/// Starts the thread writing every segment sent to it as QR code images.
+///
+/// Images are named `<path>.<n>.png`, numbered from 1 in the order of the output.
+/// The thread ends once the sender is dropped and all segments are written.
+///
+/// # Arguments
+/// * `path` - Prefix of the image paths
+pub fn spawn_encoder(path: PathBuf) -> (Sender<Vec<u8>>, JoinHandle<()>) {
+    let (sender, segments) = mpsc::channel::<Vec<u8>>();
+    let encoder = thread::spawn(move || {
+        let codes = segments.iter().flat_map(|segment| split_segment(&segment));
+        for (n, code) in (1..).zip(codes) {
+            let image = format!("{}.{n}.png", path.display());
+            if let Err(e) = encode(&code, &image) {
+                eprintln!("cli-keyhook: warning: no more QR codes are written ({e:#})");
+                break;
+            }
+        }
+    });
+
+    (sender, encoder)
+}
+
+/// Writes one QR code image with `qrencode`.
+fn encode(code: &[u8], image: &str) -> Result<()> {
+    let mut qrencode = spawn_tool(
+        Command::new("qrencode")
+            .args(["-8", "-l", "L", "-o", image])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null()),
+        "qrencode",
+    )?;
+    qrencode
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(code)?;
+
+    let status = qrencode.wait()?;
+    if !status.success() {
+        bail!("'qrencode' failed to write '{image}' ({status})");
+    }
+    Ok(())
+}
+
+/// Splits a segment into the contents of its QR codes.
+///
+/// A segment too long for one code is spread over several, each starting with
+/// `i/n` and a newline.
+fn split_segment(segment: &[u8]) -> Vec<Vec<u8>> {
+    if segment.len() <= QR_CAPACITY {
+        return vec![segment.to_vec()];
+    }
+
+    let chunks: Vec<&[u8]> = segment
+        .chunks(QR_CAPACITY - SEQUENCE_HEADER_CAPACITY)
+        .collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut code = format!("{}/{}\n", i + 1, chunks.len()).into_bytes();
+            code.extend_from_slice(chunk);
+            code
+        })
+        .collect()
+}
+
+/// Sends the output between delimiters to the encoder while passing it through unchanged.
+pub struct QrProcessor {
+    delimiter: Vec<u8>,
+    segment: Vec<u8>,
+    segments: Sender<Vec<u8>>,
+}
+
+impl QrProcessor {
+    pub fn new(delimiter: Vec<u8>, segments: Sender<Vec<u8>>) -> Self {
+        Self {
+            delimiter,
+            segment: Vec::new(),
+            segments,
+        }
+    }
+
+    /// Sends the current segment, unless it is empty.
+    fn send_segment(&mut self) {
+        let segment = std::mem::take(&mut self.segment);
+        if !segment.is_empty() {
+            let _ = self.segments.send(segment);
+        }
+    }
+}
+
+impl Processor for QrProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        for &byte in input {
+            self.segment.push(byte);
+            if self.segment.ends_with(&self.delimiter) {
+                self.segment
+                    .truncate(self.segment.len() - self.delimiter.len());
+                self.send_segment();
+            }
+        }
+
+        input.to_vec()
+    }
+}
+
+impl Drop for QrProcessor {
+    fn drop(&mut self) {
+        // The output after the last delimiter is a segment of its own
+        self.send_segment();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_codes_are_not_repeated() {
+        let mut recent = VecDeque::new();
+        assert!(remember(&mut recent, b"secret"));
+        assert!(!remember(&mut recent, b"secret"));
+        for i in 0..REMEMBERED_CODES {
+            assert!(remember(&mut recent, i.to_string().as_bytes()));
+        }
+        assert!(remember(&mut recent, b"secret"));
+    }
+
+    #[test]
+    fn segments_are_sent_between_delimiters() {
+        let (sender, segments) = mpsc::channel();
+        let mut processor = QrProcessor::new(b"\r\n".to_vec(), sender);
+        assert_eq!(processor.process(b"one\r"), b"one\r");
+        assert_eq!(processor.process(b"\n\r\ntw"), b"\n\r\ntw");
+        processor.process(b"o");
+        drop(processor);
+        assert_eq!(segments.iter().collect::<Vec<_>>(), [b"one", b"two"]);
+    }
+
+    #[test]
+    fn long_segments_are_numbered() {
+        let segment = vec![b'x'; QR_CAPACITY + 1];
+        let codes = split_segment(&segment);
+        assert_eq!(codes.len(), 2);
+        assert!(codes[0].starts_with(b"1/2\nxxx"));
+        assert!(codes[1].starts_with(b"2/2\nxxx"));
+        assert!(codes.iter().all(|code| code.len() <= QR_CAPACITY));
+        assert_eq!(
+            codes.iter().map(|code| code.len() - 4).sum::<usize>(),
+            segment.len()
+        );
+    }
+}
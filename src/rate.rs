@@ -0,0 +1,74 @@
+//! `--input-rate-estimator`: tracking how fast the user is typing.
+
+use crate::processor::Processor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Time constant of the moving average, in seconds.
+const EWMA_TIME_CONSTANT: f64 = 1.0;
+
+/// How the input rate is derived from the timing of reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RateEstimator {
+    /// Exponentially weighted moving average with a time constant of one second.
+    Ewma,
+    /// Bytes of the last read divided by the time since the previous read.
+    Instant,
+}
+
+/// The latest estimate in bytes per second, shared with whoever reports it.
+#[derive(Clone, Default)]
+pub struct InputRate(Arc<AtomicU64>);
+
+impl InputRate {
+    /// Returns the latest estimate in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, bytes_per_second: f64) {
+        self.0.store(bytes_per_second.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Updates an [`InputRate`] for every chunk while passing it through unchanged.
+pub struct RateProcessor {
+    estimator: RateEstimator,
+    rate: InputRate,
+    last_read: Option<Instant>,
+}
+
+impl RateProcessor {
+    pub fn new(estimator: RateEstimator, rate: InputRate) -> Self {
+        Self {
+            estimator,
+            rate,
+            last_read: None,
+        }
+    }
+}
+
+impl Processor for RateProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let now = Instant::now();
+        if let Some(last_read) = self.last_read.replace(now) {
+            let elapsed = now
+                .duration_since(last_read)
+                .as_secs_f64()
+                .max(f64::EPSILON);
+            let instant = input.len() as f64 / elapsed;
+
+            let estimate = match self.estimator {
+                RateEstimator::Instant => instant,
+                RateEstimator::Ewma => {
+                    let weight = 1.0 - (-elapsed / EWMA_TIME_CONSTANT).exp();
+                    self.rate.bytes_per_second() + weight * (instant - self.rate.bytes_per_second())
+                }
+            };
+            self.rate.set(estimate);
+        }
+
+        input.to_vec()
+    }
+}
@@ -0,0 +1,118 @@
+//! `--child-rss-limit`: a userspace OOM guard terminating the child when its resident
+//! memory grows too large.
+//!
+//! The `VmRSS` line of `/proc/<pid>/status` is polled, so a spike between two checks
+//! goes unnoticed; the kernel OOM killer and cgroup limits remain the hard limits.
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time the child is given to exit after SIGTERM before it is sent SIGKILL.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How often the child is checked during the grace period.
+const GRACE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest resident memory allowed to the child, and how often it is checked.
+#[derive(Clone, Copy, Debug)]
+pub struct RssLimit {
+    /// Largest `VmRSS`, in KiB
+    pub max_kib: u64,
+    /// Time between two checks
+    pub interval: Duration,
+}
+
+/// A thread polling the RSS of the child, which stops checking when dropped.
+pub struct RssGuard {
+    stopped: Arc<AtomicBool>,
+}
+
+impl RssGuard {
+    /// Spawns the thread checking the RSS of `pid` every `limit.interval`.
+    ///
+    /// Once the limit is exceeded, the child is sent SIGTERM, then SIGKILL if it is still
+    /// running after [`KILL_GRACE_PERIOD`], and the thread ends. It also ends once the
+    /// child has exited, as zombies have no `VmRSS`.
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID of the child
+    /// * `limit` - Limit enforced on the child
+    pub fn spawn(pid: Pid, limit: RssLimit) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&stopped);
+
+        thread::spawn(move || loop {
+            thread::sleep(limit.interval);
+            // Once dropped, the child may be reaped and its PID reused by another process
+            if stop.load(Ordering::Acquire) {
+                break;
+            }
+            let Some(rss) = read_rss_kib(pid) else { break };
+            if rss > limit.max_kib {
+                eprintln!(
+                    "cli-keyhook: error: the command exceeded --child-rss-limit with an RSS of {} MiB, terminating it",
+                    rss / 1024
+                );
+                terminate(pid, &stop);
+                break;
+            }
+        });
+
+        Self { stopped }
+    }
+}
+
+impl Drop for RssGuard {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// Sends SIGTERM to the child, then SIGKILL unless it exits within the grace period.
+fn terminate(pid: Pid, stop: &AtomicBool) {
+    let _ = kill(pid, Signal::SIGTERM);
+
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        thread::sleep(GRACE_CHECK_INTERVAL);
+        if stop.load(Ordering::Acquire) || read_rss_kib(pid).is_none() {
+            return;
+        }
+    }
+    let _ = kill(pid, Signal::SIGKILL);
+}
+
+/// Returns the `VmRSS` of a process in KiB, or `None` if it has exited.
+fn read_rss_kib(pid: Pid) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    parse_vm_rss(&status)
+}
+
+/// Extracts `VmRSS` from the contents of `/proc/<pid>/status`, like `VmRSS:  1234 kB`.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vm_rss_is_read_from_status() {
+        let status = "Name:\tcat\nVmPeak:\t    9000 kB\nVmRSS:\t    1964 kB\nThreads:\t1\n";
+        assert_eq!(parse_vm_rss(status), Some(1964));
+        // Zombies and kernel threads have no memory lines
+        assert_eq!(parse_vm_rss("Name:\tcat\nState:\tZ (zombie)\n"), None);
+    }
+}
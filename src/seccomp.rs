@@ -0,0 +1,68 @@
+//! `--child-seccomp-profile`: restricting the syscalls of the wrapped command.
+//!
+//! A profile is a JSON file listing the allowed syscalls:
+//!
+//! ```json
+//! {"allow": ["read", "write", "execve", "exit_group"], "default_action": "kill"}
+//! ```
+
+use anyhow::{Context, Result};
+use libseccomp::{ScmpAction, ScmpFilterContext, ScmpSyscall};
+use serde::Deserialize;
+use std::path::Path;
+
+/// What happens when the child makes a syscall missing from `allow`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DefaultAction {
+    /// Kill the whole process.
+    Kill,
+    /// Send `SIGSYS` to the calling thread.
+    Trap,
+    /// Fail the syscall with `EPERM`.
+    Errno,
+    /// Allow the syscall, but log it to the audit log.
+    Log,
+}
+
+impl From<DefaultAction> for ScmpAction {
+    fn from(action: DefaultAction) -> Self {
+        match action {
+            DefaultAction::Kill => ScmpAction::KillProcess,
+            DefaultAction::Trap => ScmpAction::Trap,
+            DefaultAction::Errno => ScmpAction::Errno(nix::libc::EPERM),
+            DefaultAction::Log => ScmpAction::Log,
+        }
+    }
+}
+
+/// Contents of a profile file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    allow: Vec<String>,
+    default_action: DefaultAction,
+}
+
+/// Reads a profile and compiles it into a filter, ready to be loaded by the child.
+///
+/// Syscall names are resolved for the native architecture, so typos are reported
+/// at startup rather than as a mysteriously killed child.
+///
+/// # Arguments
+/// * `path` - Path of the JSON profile
+pub fn load_profile(path: &Path) -> Result<ScmpFilterContext> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read seccomp profile '{}'", path.display()))?;
+    let profile: Profile = serde_json::from_str(&json)
+        .with_context(|| format!("invalid seccomp profile '{}'", path.display()))?;
+
+    let mut filter = ScmpFilterContext::new(profile.default_action.into())?;
+    for name in &profile.allow {
+        let syscall = ScmpSyscall::from_name(name)
+            .with_context(|| format!("unknown syscall '{name}' in '{}'", path.display()))?;
+        filter.add_rule(ScmpAction::Allow, syscall)?;
+    }
+
+    Ok(filter)
+}
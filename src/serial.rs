@@ -0,0 +1,134 @@
+//! `--output-to-serial` and `--input-from-serial`: bridging the PTY to a terminal on a
+//! serial line.
+
+use crate::processor::Processor;
+use anyhow::{Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::termios::{self, BaudRate, ControlFlags, SetArg};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Parses a baud rate given as a number of bits per second.
+///
+/// Only the standard rates every supported platform has are accepted.
+///
+/// # Arguments
+/// * `s` - Rate like `115200`
+///
+/// # Returns
+/// * `Ok(baud_rate)` on success
+/// * `Err(error_message)` if the rate is not a standard one
+pub fn parse_baud(s: &str) -> Result<BaudRate, String> {
+    Ok(match s.parse::<u32>().map_err(|e| format!("{e}"))? {
+        50 => BaudRate::B50,
+        75 => BaudRate::B75,
+        110 => BaudRate::B110,
+        134 => BaudRate::B134,
+        150 => BaudRate::B150,
+        200 => BaudRate::B200,
+        300 => BaudRate::B300,
+        600 => BaudRate::B600,
+        1200 => BaudRate::B1200,
+        1800 => BaudRate::B1800,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        rate => return Err(format!("unsupported baud rate {rate}")),
+    })
+}
+
+/// Opens a serial device and puts it in raw mode at the given rate, 8N1.
+///
+/// The device is opened without waiting for carrier detect, and does not become the
+/// controlling terminal of cli-keyhook.
+///
+/// # Arguments
+/// * `device` - Path of the device, like `/dev/ttyUSB0`
+/// * `baud` - Rate used in both directions
+pub fn open(device: &Path, baud: BaudRate) -> Result<File> {
+    let serial = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(nix::libc::O_NOCTTY | nix::libc::O_NONBLOCK)
+        .open(device)
+        .with_context(|| format!("cannot open serial device '{}'", device.display()))?;
+
+    let mut settings = termios::tcgetattr(&serial)
+        .with_context(|| format!("'{}' is not a serial device", device.display()))?;
+    termios::cfmakeraw(&mut settings);
+    termios::cfsetispeed(&mut settings, baud)?;
+    termios::cfsetospeed(&mut settings, baud)?;
+    // Ignore the modem control lines, which many USB adapters leave floating
+    settings.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+    termios::tcsetattr(&serial, SetArg::TCSANOW, &settings)?;
+
+    // Only needed to not block in `open`; writes should wait for the line
+    fcntl(&serial, FcntlArg::F_SETFL(OFlag::empty()))?;
+
+    Ok(serial)
+}
+
+/// Starts the thread writing output to the serial device, returning where to send it.
+///
+/// Writes happen on the thread, so that a slow line never holds up the terminal.
+/// The thread ends once the sender is dropped and everything sent is written.
+///
+/// # Arguments
+/// * `serial` - Device opened with [`open`]
+pub fn spawn_writer(mut serial: File) -> (Sender<Vec<u8>>, JoinHandle<()>) {
+    let (sender, chunks) = mpsc::channel::<Vec<u8>>();
+    let writer = thread::spawn(move || {
+        for chunk in chunks {
+            if let Err(e) = serial.write_all(&chunk) {
+                eprintln!(
+                    "cli-keyhook: warning: no more output is written to the serial device ({e})"
+                );
+                break;
+            }
+        }
+        // Lets the last bytes leave before the device is closed
+        let _ = termios::tcdrain(&serial);
+    });
+
+    (sender, writer)
+}
+
+/// Passes output through unchanged, sending a copy to the serial writer thread.
+pub struct SerialProcessor {
+    sender: Sender<Vec<u8>>,
+}
+
+impl SerialProcessor {
+    pub fn new(sender: Sender<Vec<u8>>) -> Self {
+        Self { sender }
+    }
+}
+
+impl Processor for SerialProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        let _ = self.sender.send(input.to_vec());
+        input.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_baud_rates_are_accepted() {
+        assert_eq!(parse_baud("115200"), Ok(BaudRate::B115200));
+        assert_eq!(parse_baud("9600"), Ok(BaudRate::B9600));
+        assert!(parse_baud("12345").is_err());
+        assert!(parse_baud("fast").is_err());
+    }
+}
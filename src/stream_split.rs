@@ -0,0 +1,170 @@
+//! `--output-stream-split`: teeing output lines that match a pattern to another command.
+//!
+//! Patterns are regular expressions of the `regex` crate, matched against the bytes of
+//! each line. The crate never backtracks, so matching takes linear time in the length
+//! of the line, whatever the pattern.
+
+use crate::processor::Processor;
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Parses an output stream split in the format "pattern:command".
+///
+/// The pattern ends at the first `:` that is not escaped with a backslash; `\:` is
+/// left in the pattern, where it matches a colon.
+///
+/// # Arguments
+/// * `s` - Pattern and the shell command receiving the matching lines
+///
+/// # Returns
+/// * `Ok((pattern, command))` on success
+/// * `Err(error_message)` on parsing failure
+pub fn parse_split(s: &str) -> Result<(Regex, String), String> {
+    let mut escaped = false;
+    let colon = s
+        .bytes()
+        .position(|byte| {
+            let separator = byte == b':' && !escaped;
+            escaped = byte == b'\\' && !escaped;
+            separator
+        })
+        .ok_or_else(|| {
+            format!("invalid stream split format '{s}', expected format 'pattern:command'")
+        })?;
+    let (pattern, command) = (&s[..colon], &s[colon + 1..]);
+    if command.is_empty() {
+        return Err(format!("missing command in stream split '{s}'"));
+    }
+
+    let pattern = Regex::new(pattern).map_err(|e| format!("invalid pattern '{pattern}' ({e})"))?;
+    Ok((pattern, command.to_string()))
+}
+
+/// Starts a split's command with `sh -c`, returning where to send its lines.
+///
+/// Lines are written from a thread, so that a slow command never blocks the output.
+/// Once the sender is dropped, the command's stdin is closed and the thread reaps it.
+///
+/// # Arguments
+/// * `command` - Shell command line reading the matching lines from stdin
+pub fn spawn_command(command: &str) -> Result<Sender<Vec<u8>>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run '{command}'"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+
+    let (sender, lines) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for line in lines {
+            if stdin.write_all(&line).is_err() {
+                break;
+            }
+        }
+        drop(stdin);
+        let _ = child.wait();
+    });
+
+    Ok(sender)
+}
+
+/// Passes output through unchanged, sending complete lines that match a pattern to
+/// the command of their split.
+///
+/// Lines longer than `max_line` bytes are not buffered past that length, and are
+/// never sent.
+pub struct StreamSplitProcessor {
+    splits: Vec<(Regex, Sender<Vec<u8>>)>,
+    /// The incomplete last line of the output so far
+    line: Vec<u8>,
+    max_line: usize,
+    /// Whether the current line went past `max_line`
+    overlong: bool,
+}
+
+impl StreamSplitProcessor {
+    pub fn new(splits: Vec<(Regex, Sender<Vec<u8>>)>, max_line: usize) -> Self {
+        Self {
+            splits,
+            line: Vec::new(),
+            max_line,
+            overlong: false,
+        }
+    }
+}
+
+impl Processor for StreamSplitProcessor {
+    fn process(&mut self, input: &[u8]) -> Vec<u8> {
+        for &byte in input {
+            if byte != b'\n' {
+                if self.line.len() < self.max_line {
+                    self.line.push(byte);
+                } else {
+                    self.overlong = true;
+                }
+                continue;
+            }
+
+            // Lines from the PTY end with CRLF; the commands get plain LF
+            let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+            for (pattern, sender) in self.splits.iter().filter(|_| !self.overlong) {
+                if pattern.is_match(line) {
+                    let mut tee = line.to_vec();
+                    tee.push(b'\n');
+                    let _ = sender.send(tee);
+                }
+            }
+            self.line.clear();
+            self.overlong = false;
+        }
+
+        input.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_pattern_ends_at_the_first_unescaped_colon() {
+        let (pattern, command) = parse_split(r"^a\:b:c:d").unwrap();
+        assert_eq!((pattern.as_str(), command.as_str()), (r"^a\:b", "c:d"));
+        assert!(pattern.is_match(b"a:b"));
+
+        let (pattern, command) = parse_split(r"C\\:type x").unwrap();
+        assert_eq!((pattern.as_str(), command.as_str()), (r"C\\", "type x"));
+        assert!(pattern.is_match(br"dir C\"));
+
+        assert!(parse_split("(a:cat").is_err());
+        assert!(parse_split("a\\:").is_err());
+    }
+
+    #[test]
+    fn matching_lines_are_teed_and_output_is_unchanged() {
+        let (pattern, command) = parse_split("^ERROR\\: .*:notify-send cli").unwrap();
+        assert_eq!(command, "notify-send cli");
+
+        let (sender, lines) = mpsc::channel();
+        let mut processor = StreamSplitProcessor::new(vec![(pattern, sender)], 16);
+        assert_eq!(processor.process(b"ok\r\nERROR: di"), b"ok\r\nERROR: di");
+        assert_eq!(
+            processor.process(b"sk\r\nERROR:x\r\n"),
+            b"sk\r\nERROR:x\r\n"
+        );
+        // Past the limit, a line is dropped rather than buffered
+        processor.process(b"ERROR: far too long to be teed\r\n");
+        assert!(processor.line.is_empty());
+        drop(processor);
+        assert_eq!(
+            lines.iter().collect::<Vec<_>>(),
+            [b"ERROR: disk\n".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! An in-process stand-in for the PTY setup of `run_pty_wrapper`.
+//!
+//! The user's terminal and the PTY pair are replaced by `socketpair(AF_UNIX, SOCK_STREAM)`
+//! connections, and the child by a plain `std::process::Command`. Input is queued on a
+//! channel and flushed into the fake stdin before the event loop runs on the test thread,
+//! so every test drives [`event_loop`] deterministically without forking.
+
+use crate::poller::Source;
+use crate::{event_loop, Child, ParentConfig};
+use anyhow::Result;
+use nix::unistd::Pid;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What the event loop left behind after a [`MockPty::run`].
+pub struct Outcome {
+    /// Bytes written to the PTY master, i.e. what the child would read.
+    pub to_child: Vec<u8>,
+    /// Bytes written to stdout, i.e. what the user would see.
+    pub to_stdout: Vec<u8>,
+    /// Whether the loop ended because the child exited.
+    pub child_exited: bool,
+}
+
+/// Fake terminal and PTY pair connected to a spawned stand-in child.
+pub struct MockPty {
+    /// Loop side of stdin and the simulated keyboard writing into it.
+    stdin: OwnedFd,
+    keyboard: Option<UnixStream>,
+    /// Loop side of stdout and the simulated screen reading from it.
+    stdout: OwnedFd,
+    screen: UnixStream,
+    /// Loop side of the PTY and the slave side the child would use.
+    master: OwnedFd,
+    slave: Option<UnixStream>,
+    /// Loop side of an fd received with `--fd-passing`, if any.
+    passed: Option<OwnedFd>,
+    /// Loop side of the pipe of injected input, if any.
+    injected: Option<OwnedFd>,
+    input_tx: Sender<Vec<u8>>,
+    input_rx: Receiver<Vec<u8>>,
+    child: std::process::Child,
+}
+
+impl MockPty {
+    /// Creates the socket pairs and spawns `child` as the process behind the PTY.
+    pub fn new(child: &mut Command) -> Self {
+        let (stdin, keyboard) = UnixStream::pair().expect("socketpair for stdin");
+        let (stdout, screen) = UnixStream::pair().expect("socketpair for stdout");
+        let (master, slave) = UnixStream::pair().expect("socketpair for the PTY");
+        let (input_tx, input_rx) = mpsc::channel();
+
+        Self {
+            stdin: stdin.into(),
+            keyboard: Some(keyboard),
+            stdout: stdout.into(),
+            screen,
+            master: master.into(),
+            slave: Some(slave),
+            passed: None,
+            injected: None,
+            input_tx,
+            input_rx,
+            child: child.spawn().expect("spawn stand-in child"),
+        }
+    }
+
+    /// Spawns a child that stays alive for the whole test.
+    pub fn with_idle_child() -> Self {
+        Self::new(Command::new("sleep").arg("60"))
+    }
+
+    /// Returns a sender simulating the user typing; each message is one `write` to stdin.
+    pub fn keyboard(&self) -> Sender<Vec<u8>> {
+        self.input_tx.clone()
+    }
+
+    /// Simulates the child writing `bytes` to its terminal.
+    pub fn child_writes(&mut self, bytes: &[u8]) {
+        self.slave
+            .as_mut()
+            .expect("slave is open")
+            .write_all(bytes)
+            .expect("write to slave");
+    }
+
+    /// Simulates an fd received with `--fd-passing` and returns its writing side.
+    pub fn pass_fd(&mut self) -> UnixStream {
+        let (passed, sender) = UnixStream::pair().expect("socketpair for the passed fd");
+        self.passed = Some(passed.into());
+        sender
+    }
+
+    /// Simulates a regular file received with `--fd-passing`.
+    pub fn pass_file(&mut self, file: File) {
+        self.passed = Some(file.into());
+    }
+
+    /// Simulates the pipe of injected input and returns its writing side.
+    pub fn injector(&mut self) -> UnixStream {
+        let (injected, injector) = UnixStream::pair().expect("socketpair for injected input");
+        self.injected = Some(injected.into());
+        injector
+    }
+
+    /// Simulates the user's terminal closing.
+    pub fn close_stdin(&mut self) {
+        self.flush_keyboard();
+        self.keyboard = None;
+    }
+
+    /// Simulates the child closing its terminal.
+    pub fn close_slave(&mut self) {
+        self.slave = None;
+    }
+
+    /// Process ID of the stand-in child.
+    pub fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as i32)
+    }
+
+    /// Flushes the queued keyboard input and runs the event loop until it ends.
+    pub fn run(mut self, config: &ParentConfig) -> Result<Outcome> {
+        self.flush_keyboard();
+
+        let mut inputs = vec![(Source::Stdin, self.stdin.as_fd())];
+        if let Some(passed) = &self.passed {
+            inputs.push((Source::Passed, passed.as_fd()));
+        }
+        if let Some(injected) = &self.injected {
+            inputs.push((Source::Injected, injected.as_fd()));
+        }
+
+        let (mut input, mut output) = config.chains();
+        let child_exited = event_loop(
+            &inputs,
+            self.stdout.as_fd(),
+            &self.master,
+            Child::Forked(self.pid()),
+            &mut input,
+            &mut output,
+            config,
+        )?;
+        if !child_exited {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+
+        // Close the loop sides so that reading the peers stops at their end
+        drop(self.stdin);
+        drop(self.stdout);
+        drop(self.master);
+        self.keyboard = None;
+
+        let mut to_child = Vec::new();
+        if let Some(mut slave) = self.slave.take() {
+            slave.read_to_end(&mut to_child)?;
+        }
+        let mut to_stdout = Vec::new();
+        self.screen.read_to_end(&mut to_stdout)?;
+
+        Ok(Outcome {
+            to_child,
+            to_stdout,
+            child_exited,
+        })
+    }
+
+    fn flush_keyboard(&mut self) {
+        let Some(keyboard) = self.keyboard.as_mut() else {
+            return;
+        };
+        for input in self.input_rx.try_iter() {
+            keyboard.write_all(&input).expect("write to stdin");
+        }
+    }
+}